@@ -0,0 +1,50 @@
+//! Parsing the sample files, run with `cargo +nightly bench`
+//!
+//! Parsing with a subscriber that has every level disabled should take about as long as parsing without one,
+//! the log messages of the hot path must not be formatted when they are not recorded.
+//! Likewise a time budget should not slow down parsing, the clock is only checked now and then.
+
+#![feature(test)]
+
+extern crate test;
+
+use gpmf::byteorder_gpmf::{parse_gpmf, parse_gpmf_opts, ParseOptions};
+use std::path::Path;
+use std::time::Duration;
+use test::Bencher;
+use tracing::level_filters::LevelFilter;
+
+/// The bytes of a sample file
+fn sample(name: &str) -> Vec<u8> {
+    std::fs::read(Path::new("samples").join(name)).unwrap()
+}
+
+#[bench]
+fn bench_parse_no_subscriber(b: &mut Bencher) {
+    let text = sample("hero5.raw");
+    b.bytes = text.len() as u64;
+    b.iter(|| parse_gpmf(test::black_box(&text)).unwrap());
+}
+
+#[bench]
+fn bench_parse_disabled_subscriber(b: &mut Bencher) {
+    let text = sample("hero5.raw");
+    b.bytes = text.len() as u64;
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::OFF)
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        b.iter(|| parse_gpmf(test::black_box(&text)).unwrap());
+    });
+}
+
+#[bench]
+fn bench_parse_time_budget(b: &mut Bencher) {
+    let text = sample("hero5.raw");
+    b.bytes = text.len() as u64;
+    let options = ParseOptions {
+        time_budget: Some(Duration::from_secs(3600)),
+        ..Default::default()
+    };
+    b.iter(|| parse_gpmf_opts(test::black_box(&text), &options).unwrap());
+}
@@ -0,0 +1,513 @@
+//! Building the DEVC and STRM hierarchy of a device, e.g. to generate telemetry for testing
+//!
+//! ```
+//! use gpmf::builder::GpmfBuilder;
+//! use gpmf::Tag;
+//!
+//! let accl = [[4084_i16, -76, 387], [4081, -150, 404]];
+//! let bytes = GpmfBuilder::device(1, "My Sensor")
+//!     .stream("Accelerometer (m/s²)", |s| s.scale(&[418]).samples_i16(Tag::ACCL, &accl))
+//!     .to_bytes()?;
+//! # Ok::<(), gpmf::builder::BuildError>(())
+//! ```
+
+use crate::byteorder_gpmf::{write_gpmf, WriteError};
+use crate::{KeyValue, Tag, Value};
+use std::fmt;
+
+/// Errors returned when building a device
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// A SCAL with neither one value nor one value per element of the samples
+    ScaleMismatch {
+        /// The name of the stream
+        stream: String,
+        /// The number of scale values
+        scale: usize,
+        /// The number of elements in each sample
+        width: usize,
+    },
+    /// A stream without samples
+    MissingSamples {
+        /// The name of the stream
+        stream: String,
+    },
+    /// A stream with more than one set of samples
+    DuplicateSamples {
+        /// The name of the stream
+        stream: String,
+    },
+    /// The entries can not be written
+    Write(WriteError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::ScaleMismatch {
+                stream,
+                scale,
+                width,
+            } => write!(
+                f,
+                "Stream {:?} has {} scale values for samples of {} elements",
+                stream, scale, width
+            ),
+            BuildError::MissingSamples { stream } => {
+                write!(f, "Stream {:?} has no samples", stream)
+            }
+            BuildError::DuplicateSamples { stream } => {
+                write!(f, "Stream {:?} has more than one set of samples", stream)
+            }
+            BuildError::Write(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::Write(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<WriteError> for BuildError {
+    fn from(err: WriteError) -> Self {
+        BuildError::Write(err)
+    }
+}
+
+/// Builds one DEVC with its DVID, DVNM and streams in the order GoPro cameras write them
+#[derive(Debug, Clone)]
+pub struct GpmfBuilder {
+    /// The device ID for DVID
+    id: u32,
+    /// The device name for DVNM
+    name: String,
+    /// The streams in order
+    streams: Vec<StreamBuilder>,
+}
+
+impl GpmfBuilder {
+    /// Start a device, a payload with several devices is the concatenation of their entries
+    pub fn device(id: u32, name: &str) -> Self {
+        GpmfBuilder {
+            id,
+            name: name.to_string(),
+            streams: Vec::new(),
+        }
+    }
+
+    /// Add a stream named `name` for STNM, with its entries set by `build`
+    pub fn stream(
+        mut self,
+        name: &str,
+        build: impl FnOnce(StreamBuilder) -> StreamBuilder,
+    ) -> Self {
+        self.streams.push(build(StreamBuilder::new(name)));
+        self
+    }
+
+    /// The DEVC entry
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a stream has no samples, or a SCAL that does not match its samples
+    pub fn build(self) -> Result<Vec<KeyValue>, BuildError> {
+        self.build_with(None)
+    }
+
+    /// The DEVC entry written as GPMF
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`GpmfBuilder::build`], or [`BuildError::Write`] if the samples do not fit a KLV entry
+    pub fn to_bytes(self) -> Result<Vec<u8>, BuildError> {
+        Ok(write_gpmf(&self.build()?)?)
+    }
+
+    /// The DEVC entry of one payload of a recording, with TSMP and EMPT of each stream computed by `counters`
+    ///
+    /// TSMP is the total number of samples of the stream in this and the previous payloads built with `counters`,
+    /// EMPT the number of those payloads without samples and only written once there has been one.
+    /// The samples of a stream may be empty, its data entry is then left out.
+    /// The counters are only updated if the payload is built.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`GpmfBuilder::build`]
+    pub fn build_payload(self, counters: &mut SampleCounters) -> Result<Vec<KeyValue>, BuildError> {
+        self.build_with(Some(counters))
+    }
+
+    /// The DEVC entry of one payload written as GPMF, see [`GpmfBuilder::build_payload`]
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`GpmfBuilder::to_bytes`]
+    pub fn to_payload_bytes(self, counters: &mut SampleCounters) -> Result<Vec<u8>, BuildError> {
+        Ok(write_gpmf(&self.build_payload(counters)?)?)
+    }
+
+    /// The DEVC entry, counting the samples of its streams when building a payload
+    fn build_with(
+        self,
+        counters: Option<&mut SampleCounters>,
+    ) -> Result<Vec<KeyValue>, BuildError> {
+        let mut children = vec![
+            KeyValue::new(Tag::DVID, Value::Simple(vec![vec![Value::U32(self.id)]])),
+            KeyValue::new(Tag::DVNM, Value::String(self.name)),
+        ];
+        let mut counted = counters.as_deref().cloned();
+        for mut stream in self.streams {
+            if let (Some(counted), Some((tag, samples))) = (&mut counted, &stream.samples) {
+                let counter = counted.counter(self.id, tag);
+                let delivered = u32::try_from(samples.len()).unwrap_or(u32::MAX);
+                counter.total = counter.total.saturating_add(delivered);
+                counter.empty += u32::from(samples.is_empty());
+                stream.tsmp = Some(counter.total);
+                stream.empt = (counter.empty > 0).then_some(counter.empty);
+            }
+            children.push(stream.build()?);
+        }
+        if let (Some(counters), Some(counted)) = (counters, counted) {
+            *counters = counted;
+        }
+        Ok(vec![KeyValue::new(Tag::DEVC, Value::Nested(children))])
+    }
+}
+
+/// The TSMP and EMPT of each stream across the payloads of a recording, see [`GpmfBuilder::build_payload`]
+#[derive(Debug, Clone, Default)]
+pub struct SampleCounters {
+    /// The counters by device ID and FourCC of the samples
+    streams: Vec<(u32, Tag, StreamCounter)>,
+}
+
+/// The counts of one stream in [`SampleCounters`]
+#[derive(Debug, Clone, Copy, Default)]
+struct StreamCounter {
+    /// The samples delivered so far, for TSMP
+    total: u32,
+    /// The payloads without samples so far, for EMPT
+    empty: u32,
+}
+
+impl SampleCounters {
+    /// The counter of the stream of device `id` with samples `tag`
+    fn counter(&mut self, id: u32, tag: &Tag) -> &mut StreamCounter {
+        let index = match self
+            .streams
+            .iter()
+            .position(|(stream_id, stream_tag, _)| *stream_id == id && stream_tag == tag)
+        {
+            Some(index) => index,
+            None => {
+                self.streams
+                    .push((id, tag.clone(), StreamCounter::default()));
+                self.streams.len() - 1
+            }
+        };
+        &mut self.streams[index].2
+    }
+
+    /// The total number of samples of the stream of device `id` with samples `tag`, its last TSMP
+    pub fn total_samples(&self, id: u32, tag: &Tag) -> u32 {
+        self.streams
+            .iter()
+            .find(|(stream_id, stream_tag, _)| *stream_id == id && stream_tag == tag)
+            .map_or(0, |(_, _, counter)| counter.total)
+    }
+}
+
+/// Builds one STRM, see [`GpmfBuilder::stream`]
+///
+/// Like the cameras write it, TSMP and EMPT come first, then STNM, SIUN and SCAL, and the samples last.
+/// TSMP is the number of samples unless one is given.
+#[derive(Debug, Clone)]
+pub struct StreamBuilder {
+    /// The stream name for STNM
+    name: String,
+    /// The total number of samples for TSMP
+    tsmp: Option<u32>,
+    /// The number of empty payloads for EMPT
+    empt: Option<u32>,
+    /// The standard units for SIUN
+    units: Option<String>,
+    /// The divisors for SCAL
+    scale: Option<Vec<i32>>,
+    /// The FourCC and samples of the sensor data
+    samples: Option<(Tag, Vec<Vec<Value>>)>,
+    /// Whether the samples were given more than once
+    duplicate: bool,
+}
+
+impl StreamBuilder {
+    /// An empty stream named `name`
+    fn new(name: &str) -> Self {
+        StreamBuilder {
+            name: name.to_string(),
+            tsmp: None,
+            empt: None,
+            units: None,
+            scale: None,
+            samples: None,
+            duplicate: false,
+        }
+    }
+
+    /// The total number of samples delivered so far for TSMP, when it differs from the samples of this payload.
+    /// [`GpmfBuilder::build_payload`] computes it instead
+    pub fn tsmp(mut self, total: u32) -> Self {
+        self.tsmp = Some(total);
+        self
+    }
+
+    /// The standard units of the samples for SIUN, e.g. `m/s²`
+    pub fn units(mut self, units: &str) -> Self {
+        self.units = Some(units.to_string());
+        self
+    }
+
+    /// The divisors for SCAL, one for all elements or one per element of the samples
+    pub fn scale(mut self, scale: &[i32]) -> Self {
+        self.scale = Some(scale.to_vec());
+        self
+    }
+
+    /// The samples of the sensor data with key `tag`, each a sample of elements of the same type
+    pub fn samples(mut self, tag: Tag, samples: Vec<Vec<Value>>) -> Self {
+        self.duplicate |= self.samples.is_some();
+        self.samples = Some((tag, samples));
+        self
+    }
+
+    /// 16 bit samples, e.g. the three axes of ACCL or GYRO
+    pub fn samples_i16<const N: usize>(self, tag: Tag, samples: &[[i16; N]]) -> Self {
+        let samples = samples
+            .iter()
+            .map(|sample| sample.iter().map(|v| Value::I16(*v)).collect())
+            .collect();
+        self.samples(tag, samples)
+    }
+
+    /// 32 bit samples, e.g. the latitude, longitude, altitude and speeds of GPS5
+    pub fn samples_i32<const N: usize>(self, tag: Tag, samples: &[[i32; N]]) -> Self {
+        let samples = samples
+            .iter()
+            .map(|sample| sample.iter().map(|v| Value::I32(*v)).collect())
+            .collect();
+        self.samples(tag, samples)
+    }
+
+    /// Floating point samples, e.g. CORI quaternions
+    pub fn samples_f32<const N: usize>(self, tag: Tag, samples: &[[f32; N]]) -> Self {
+        let samples = samples
+            .iter()
+            .map(|sample| sample.iter().map(|v| Value::F32(*v)).collect())
+            .collect();
+        self.samples(tag, samples)
+    }
+
+    /// The STRM entry
+    fn build(self) -> Result<KeyValue, BuildError> {
+        if self.duplicate {
+            return Err(BuildError::DuplicateSamples { stream: self.name });
+        }
+        let Some((tag, samples)) = self.samples else {
+            return Err(BuildError::MissingSamples { stream: self.name });
+        };
+        let width = samples.first().map_or(0, Vec::len);
+        if let Some(scale) = &self.scale {
+            if !samples.is_empty() && scale.len() != 1 && scale.len() != width {
+                return Err(BuildError::ScaleMismatch {
+                    stream: self.name,
+                    scale: scale.len(),
+                    width,
+                });
+            }
+        }
+
+        let tsmp = self.tsmp.unwrap_or(samples.len() as u32);
+        let mut entries = vec![
+            KeyValue::new(Tag::TSMP, Value::Simple(vec![vec![Value::U32(tsmp)]])),
+            KeyValue::new(Tag::STNM, Value::String(self.name)),
+        ];
+        if let Some(empt) = self.empt {
+            entries.insert(
+                1,
+                KeyValue::new(Tag::EMPT, Value::Simple(vec![vec![Value::U32(empt)]])),
+            );
+        }
+        if let Some(units) = self.units {
+            entries.push(KeyValue::new(Tag::SIUN, Value::String(units)));
+        }
+        if let Some(scale) = self.scale {
+            // one sample per divisor, like the cameras write it
+            let scale = scale.into_iter().map(|v| vec![Value::I32(v)]).collect();
+            entries.push(KeyValue::new(Tag::SCAL, Value::Simple(scale)));
+        }
+        if !samples.is_empty() {
+            entries.push(KeyValue::new(tag, Value::Simple(samples)));
+        }
+        Ok(KeyValue::new(Tag::STRM, Value::Nested(entries)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::byteorder_gpmf::parse_gpmf;
+
+    /// The value of an entry with a single U32 like TSMP
+    fn single_u32(kv: &KeyValue) -> Option<u32> {
+        match kv.value() {
+            Value::Simple(samples) => match samples[..] {
+                [ref sample] => match sample[..] {
+                    [Value::U32(v)] => Some(v),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_build_roundtrip() {
+        let accl = [[4084_i16, -76, 387], [4081, -150, 404], [4093, -160, 399]];
+        let gps5 = [[335_068_213_i32, -1_179_453_490, 6342, 0, 12]];
+        let bytes = GpmfBuilder::device(1, "My Sensor")
+            .stream("Accelerometer (m/s²)", |s| {
+                s.units("m/s²").scale(&[418]).samples_i16(Tag::ACCL, &accl)
+            })
+            .stream("GPS", |s| {
+                s.tsmp(100)
+                    .scale(&[10_000_000, 10_000_000, 1000, 1000, 100])
+                    .samples_i32(Tag::GPS5, &gps5)
+            })
+            .to_bytes()
+            .unwrap();
+
+        let entries = parse_gpmf(&bytes).unwrap();
+        assert_eq!(entries.len(), 1);
+        let Value::Nested(device) = entries[0].value() else {
+            panic!("Expected DEVC, got {:?}", entries[0]);
+        };
+        let keys: Vec<_> = device.iter().map(|kv| kv.key().fourcc()).collect();
+        assert_eq!(keys, ["DVID", "DVNM", "STRM", "STRM"]);
+        assert!(matches!(device[1].value(), Value::String(name) if name == "My Sensor"));
+
+        let Value::Nested(accl_stream) = device[2].value() else {
+            panic!("Expected STRM, got {:?}", device[2]);
+        };
+        // TSMP before STNM, the samples last
+        let keys: Vec<_> = accl_stream.iter().map(|kv| kv.key().fourcc()).collect();
+        assert_eq!(keys, ["TSMP", "STNM", "SIUN", "SCAL", "ACCL"]);
+        assert_eq!(single_u32(&accl_stream[0]), Some(3));
+        assert!(
+            matches!(accl_stream[1].value(), Value::String(name) if name == "Accelerometer (m/s²)")
+        );
+        let Value::Simple(samples) = accl_stream[4].value() else {
+            panic!("Expected ACCL samples, got {:?}", accl_stream[4]);
+        };
+        assert_eq!(samples.len(), accl.len());
+        for (sample, expected) in samples.iter().zip(&accl) {
+            for (value, expected) in sample.iter().zip(expected) {
+                assert!(matches!(value, Value::I16(v) if v == expected));
+            }
+        }
+
+        let Value::Nested(gps_stream) = device[3].value() else {
+            panic!("Expected STRM, got {:?}", device[3]);
+        };
+        assert_eq!(single_u32(&gps_stream[0]), Some(100));
+        assert_eq!(gps_stream[2].key(), &Tag::SCAL);
+        assert_eq!(gps_stream[3].elements_per_sample(), Some(5));
+    }
+
+    #[test]
+    fn test_payload_counters() {
+        let gps5 = [[335_068_213_i32, -1_179_453_490, 6342, 0, 12]; 10];
+        let mut counters = SampleCounters::default();
+        let mut streams = Vec::new();
+        for samples in [&gps5[..], &[], &gps5[..5]] {
+            let bytes = GpmfBuilder::device(1, "Camera")
+                .stream("GPS", |s| {
+                    s.scale(&[10_000_000, 10_000_000, 1000, 1000, 100])
+                        .samples_i32(Tag::GPS5, samples)
+                })
+                .to_payload_bytes(&mut counters)
+                .unwrap();
+            let entries = parse_gpmf(&bytes).unwrap();
+            let Value::Nested(device) = entries[0].value() else {
+                panic!("Expected DEVC, got {:?}", entries[0]);
+            };
+            let Value::Nested(stream) = device[2].value() else {
+                panic!("Expected STRM, got {:?}", device[2]);
+            };
+            streams.push(stream.clone());
+        }
+
+        let find = |stream: &[KeyValue], tag: Tag| {
+            stream
+                .iter()
+                .find(|kv| kv.key() == &tag)
+                .and_then(single_u32)
+        };
+        let tsmp: Vec<_> = streams.iter().map(|s| find(s, Tag::TSMP)).collect();
+        assert_eq!(tsmp, [Some(10), Some(10), Some(15)]);
+        let empt: Vec<_> = streams.iter().map(|s| find(s, Tag::EMPT)).collect();
+        assert_eq!(empt, [None, Some(1), Some(1)]);
+        let keys: Vec<_> = streams[2].iter().map(|kv| kv.key().fourcc()).collect();
+        assert_eq!(keys, ["TSMP", "EMPT", "STNM", "SCAL", "GPS5"]);
+        assert!(!streams[1].iter().any(|kv| kv.key() == &Tag::GPS5));
+        assert_eq!(counters.total_samples(1, &Tag::GPS5), 15);
+
+        // a payload that fails to build is not counted
+        let failed = GpmfBuilder::device(1, "Camera")
+            .stream("GPS", |s| s.scale(&[1, 2]).samples_i32(Tag::GPS5, &gps5))
+            .build_payload(&mut counters);
+        assert!(failed.is_err());
+        assert_eq!(counters.total_samples(1, &Tag::GPS5), 15);
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let accl = [[1_i16, 2, 3]];
+        let mismatch = GpmfBuilder::device(1, "My Sensor")
+            .stream("Accelerometer", |s| {
+                s.scale(&[1, 2]).samples_i16(Tag::ACCL, &accl)
+            })
+            .build();
+        assert!(matches!(
+            mismatch,
+            Err(BuildError::ScaleMismatch {
+                scale: 2,
+                width: 3,
+                ..
+            })
+        ));
+
+        let missing = GpmfBuilder::device(1, "My Sensor")
+            .stream("Accelerometer", |s| s.scale(&[418]))
+            .build();
+        assert!(
+            matches!(missing, Err(BuildError::MissingSamples { stream }) if stream == "Accelerometer")
+        );
+
+        let duplicate = GpmfBuilder::device(1, "My Sensor")
+            .stream("Accelerometer", |s| {
+                s.samples_i16(Tag::ACCL, &accl)
+                    .samples_i16(Tag::ACCL, &accl)
+            })
+            .build();
+        assert!(matches!(
+            duplicate,
+            Err(BuildError::DuplicateSamples { .. })
+        ));
+    }
+}
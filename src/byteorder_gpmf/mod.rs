@@ -1,159 +1,1342 @@
 //! This module implements the GPMF parser using the byteorder crate
 
-use crate::{KeyValue, Tag};
-use crate::{Type, Value, DATE_FORMAT};
+#[cfg(feature = "dates")]
+use crate::DATE_FORMAT;
+use crate::{Header, KeyValue, Tag};
+use crate::{Type, Value};
 use byteorder::{BigEndian, ReadBytesExt};
+#[cfg(feature = "dates")]
 use chrono::{TimeZone, Utc};
+#[cfg(feature = "fixedpoint")]
 use fixed::types::{I16F16, I32F32};
+use std::fmt;
 use std::io;
 use std::io::{BufRead, Cursor, Read};
+use std::ops::Range;
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
+use strum::Display;
 use tracing::{debug, enabled, error, info, span, trace, warn, Level};
+
+pub mod write;
+pub use write::{write_gpmf, write_gpmf_opts, GpmfWriter, WriteError, WriteOptions};
 // use tracing_error::{InstrumentResult, TracedError};
 
 impl Type {
-    /// Implement reading Data Type using the byteorder crate
-    fn read(&self, input: &mut Cursor<&[u8]>) -> anyhow::Result<Value> {
+    /// Implement reading Data Type using the byteorder crate,
+    /// `base_offset` is the position of the cursor's buffer within the top level buffer
+    fn read(&self, input: &mut Cursor<&[u8]>, base_offset: u64) -> Result<Value, GpmfError> {
+        let offset = base_offset + input.position();
+        let at = |err| GpmfError::io(err, offset);
         let val = match self {
-            Type::I8 => Value::I8(input.read_i8()?),
-            Type::U8 => Value::U8(input.read_u8()?),
-            Type::Char => Value::Char(input.read_u8()? as char),
-            Type::F64 => Value::F64(input.read_f64::<BigEndian>()?),
-            Type::F32 => Value::F32(input.read_f32::<BigEndian>()?),
+            Type::I8 => Value::I8(input.read_i8().map_err(at)?),
+            Type::U8 => Value::U8(input.read_u8().map_err(at)?),
+            Type::Char => Value::Char(input.read_u8().map_err(at)? as char),
+            Type::F64 => Value::F64(input.read_f64::<BigEndian>().map_err(at)?),
+            Type::F32 => Value::F32(input.read_f32::<BigEndian>().map_err(at)?),
             Type::FourCC => {
-                let fourcc = read_tag(input)?;
+                let fourcc = read_tag(input, base_offset)?;
                 Value::Tag(fourcc)
             }
-            Type::U128 => Value::U128(input.read_u128::<BigEndian>()?),
-            Type::I64 => Value::I64(input.read_i64::<BigEndian>()?),
-            Type::U64 => Value::U64(input.read_u64::<BigEndian>()?),
-            Type::I32 => Value::I32(input.read_i32::<BigEndian>()?),
-            Type::U32 => Value::U32(input.read_u32::<BigEndian>()?),
+            Type::U128 => Value::U128(input.read_u128::<BigEndian>().map_err(at)?),
+            Type::I64 => Value::I64(input.read_i64::<BigEndian>().map_err(at)?),
+            Type::U64 => Value::U64(input.read_u64::<BigEndian>().map_err(at)?),
+            Type::I32 => Value::I32(input.read_i32::<BigEndian>().map_err(at)?),
+            Type::U32 => Value::U32(input.read_u32::<BigEndian>().map_err(at)?),
+            #[cfg(feature = "fixedpoint")]
             Type::Fixed32 => {
                 let mut buf = [0u8; 4];
-                input.read_exact(&mut buf)?;
+                input.read_exact(&mut buf).map_err(at)?;
                 Value::Fixed32(I16F16::from_be_bytes(buf))
             }
+            #[cfg(not(feature = "fixedpoint"))]
+            Type::Fixed32 => Value::Fixed32(input.read_i32::<BigEndian>().map_err(at)?),
+            #[cfg(feature = "fixedpoint")]
             Type::Fixed64 => {
                 let mut buf = [0u8; 8];
-                input.read_exact(&mut buf)?;
+                input.read_exact(&mut buf).map_err(at)?;
                 Value::Fixed64(I32F32::from_be_bytes(buf))
             }
-            Type::I16 => Value::I16(input.read_i16::<BigEndian>()?),
-            Type::U16 => Value::U16(input.read_u16::<BigEndian>()?),
+            #[cfg(not(feature = "fixedpoint"))]
+            Type::Fixed64 => Value::Fixed64(input.read_i64::<BigEndian>().map_err(at)?),
+            Type::I16 => Value::I16(input.read_i16::<BigEndian>().map_err(at)?),
+            Type::U16 => Value::U16(input.read_u16::<BigEndian>().map_err(at)?),
             Type::Date => {
                 let mut buf = [0u8; 16];
-                input.read_exact(&mut buf)?;
-                let date_str = String::from_utf8_lossy(&buf);
-                let utc = Utc
-                    .datetime_from_str(date_str.as_ref(), DATE_FORMAT)
-                    .unwrap();
-                Value::Date(utc)
+                input.read_exact(&mut buf).map_err(at)?;
+                decode_date(buf)
             }
-            _ => {
-                unimplemented!("For Type {} please file a bug report", self)
+            Type::Complex | Type::Nested => {
+                return Err(GpmfError::UnreadableType { typ: *self, offset });
             }
         };
         Ok(val)
     }
 }
 
+/// Decode the 16 bytes of a **U** date, telling the placeholders written before GPS lock apart from timestamps
+fn decode_date(buf: [u8; 16]) -> Value {
+    // zeros, spaces or "000000000000.000" depending on the camera
+    if buf.iter().all(|b| matches!(b, 0 | b' ' | b'0' | b'.')) {
+        debug!("No date {:?}", buf);
+        return Value::NoDate(buf);
+    }
+    #[cfg(feature = "dates")]
+    {
+        let date_str = String::from_utf8_lossy(&buf);
+        // short dates are padded with NULs or spaces
+        let trimmed = date_str.trim_end_matches(['\0', ' ']);
+        match Utc.datetime_from_str(trimmed, DATE_FORMAT) {
+            Ok(utc) => Value::Date(utc),
+            Err(err) => {
+                warn!("Invalid date {:?}, keeping raw bytes: {}", date_str, err);
+                Value::RawDate(buf)
+            }
+        }
+    }
+    #[cfg(not(feature = "dates"))]
+    Value::Date(buf)
+}
+
+/// Whether the bytes have the yymmddhhmmss.sss shape of a date, for when dates are not parsed
+#[cfg(not(feature = "dates"))]
+fn is_date_shaped(buf: &[u8; 16]) -> bool {
+    buf.iter().enumerate().all(|(i, b)| {
+        if i == 12 {
+            *b == b'.'
+        } else {
+            b.is_ascii_digit()
+        }
+    })
+}
+
 /// Read the FourCC field using the byteorder crate
-fn read_tag(input: &mut Cursor<&[u8]>) -> anyhow::Result<Tag> {
+fn read_tag(input: &mut Cursor<&[u8]>, base_offset: u64) -> Result<Tag, GpmfError> {
+    let mut fourcc = [0u8; 4];
+    read_at(input, base_offset, |input| input.read_exact(&mut fourcc))?;
+    Ok(tag_from_fourcc(fourcc))
+}
+
+/// Read the FourCC key of a KLV entry.
+/// Keys are always printable ASCII, anything else means the input is corrupt or the cursor lost sync
+fn read_key(input: &mut Cursor<&[u8]>, base_offset: u64) -> Result<Tag, GpmfError> {
+    let offset = base_offset + input.position();
     let mut fourcc = [0u8; 4];
-    input.read_exact(fourcc.as_mut_slice())?;
-    let tag_string: String = fourcc.iter().map(|c| *c as char).collect();
-    let tag = Tag::try_from(tag_string.as_str())?;
-    debug!("Tag {} ({:?})", tag_string, tag);
-    if let Tag::Other(other) = &tag {
-        warn!("Unsupported tag found {}", other);
+    read_at(input, base_offset, |input| input.read_exact(&mut fourcc))?;
+    if !fourcc.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        return Err(GpmfError::InvalidFourCC { fourcc, offset });
+    }
+    Ok(tag_from_fourcc(fourcc))
+}
+
+/// Look up the tag of a FourCC, logging unsupported ones
+fn tag_from_fourcc(fourcc: [u8; 4]) -> Tag {
+    let tag = Tag::from_fourcc(fourcc);
+    debug!("Tag {:?}", tag);
+    if let Tag::Other(_) = &tag {
+        warn!("Unsupported tag found {}", tag);
+    }
+    tag
+}
+
+/// Run `read` on the cursor, reporting a failure at the position the read started
+fn read_at<T>(
+    input: &mut Cursor<&[u8]>,
+    base_offset: u64,
+    read: impl FnOnce(&mut Cursor<&[u8]>) -> io::Result<T>,
+) -> Result<T, GpmfError> {
+    let offset = base_offset + input.position();
+    read(input).map_err(|err| GpmfError::io(err, offset))
+}
+
+/// Limits on the work and memory a single parse may use, protecting against malicious input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// Maximum number of decoded values over the whole input, each sample element and each string counts as one
+    pub max_total_values: usize,
+    /// Maximum number of nested containers inside each other, real streams only nest DEVC and STRM
+    pub max_nesting: usize,
+    /// Maximum length in bytes of a single string
+    pub max_string_len: usize,
+    /// Maximum number of data bytes (size times repeat) of a single entry
+    pub max_payload_bytes: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            max_total_values: 16 * 1024 * 1024,
+            max_nesting: 16,
+            max_string_len: u16::MAX as usize,
+            max_payload_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// How strictly the input has to follow the GPMF specification
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Reject anything non-conforming, including entries with unknown types
+    Strict,
+    /// Keep entries with unknown types as [`Value::Raw`] but fail on entries that can not be decoded
+    #[default]
+    Standard,
+    /// Keep entries with a misaligned size or without a TYPE definition as [`Value::Raw`] and report a [`GpmfWarning`]
+    Lenient,
+}
+
+/// What to do with entries whose tag is not a known [`Tag`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownTags {
+    /// Keep them as [`Tag::Other`]
+    #[default]
+    Keep,
+    /// Leave them out of the result
+    Skip,
+    /// Fail with [`GpmfError::UnknownTag`]
+    Reject,
+}
+
+/// Options controlling how the GPMF stream is parsed
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Maximum wall clock time to spend parsing.
+    ///
+    /// The budget is checked before every nested container, like the DEVC of each payload, and every
+    /// [`BUDGET_CHECK_INTERVAL`] entries in between, so it can be overshot by at most the time taken
+    /// to decode that many entries.
+    pub time_budget: Option<Duration>,
+    /// Limits on the size of the input, exceeding one is a [`LimitExceeded`] error
+    pub limits: ParseLimits,
+    /// After an error skip ahead to the next plausible DEVC or STRM header and continue parsing there.
+    ///
+    /// Each skip is reported as a [`GpmfWarning`] by [`parse_gpmf_with_warnings`].
+    /// Running out of time or exceeding a limit is never skipped.
+    pub resync: bool,
+    /// How to handle entries that do not follow the specification
+    pub strictness: Strictness,
+    /// How to handle entries with unknown tags
+    pub unknown_tags: UnknownTags,
+    /// Keep the data bytes and padding of every entry as they were read,
+    /// so [`write_gpmf_opts`] with [`WriteOptions::exact`] reproduces the input byte for byte.
+    ///
+    /// This roughly doubles the memory used by the entries. Bytes skipped because of an error are not kept.
+    pub exact: bool,
+}
+
+impl ParseOptions {
+    /// Options for rejecting anything non-conforming, including unknown tags
+    pub fn strict() -> Self {
+        Self {
+            strictness: Strictness::Strict,
+            unknown_tags: UnknownTags::Reject,
+            ..Default::default()
+        }
+    }
+
+    /// Options for salvaging as much as possible, resyncing after errors
+    pub fn lenient() -> Self {
+        Self {
+            resync: true,
+            strictness: Strictness::Lenient,
+            ..Default::default()
+        }
+    }
+}
+
+/// Error returned when parsing runs longer than [`ParseOptions::time_budget`]
+#[derive(Debug)]
+pub struct BudgetExceeded {
+    /// The entries parsed before the budget ran out, nested containers are truncated at the same point
+    pub partial: Vec<KeyValue>,
+    /// The byte offset in the input at which parsing stopped
+    pub offset: u64,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Parse time budget exceeded at offset {}", self.offset)
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+/// One of the [`ParseLimits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[non_exhaustive]
+pub enum Limit {
+    /// [`ParseLimits::max_total_values`]
+    TotalValues,
+    /// [`ParseLimits::max_nesting`]
+    Nesting,
+    /// [`ParseLimits::max_string_len`]
+    StringLen,
+    /// [`ParseLimits::max_payload_bytes`]
+    PayloadBytes,
+}
+
+/// Error returned when the input exceeds one of the [`ParseLimits`]
+#[derive(Debug)]
+pub struct LimitExceeded {
+    /// The limit that was hit
+    pub limit: Limit,
+    /// The configured value of the limit
+    pub max: usize,
+    /// The byte offset in the input of the entry exceeding the limit
+    pub offset: u64,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} limit of {} exceeded at offset {}",
+            self.limit, self.max, self.offset
+        )
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// The number of entries parsed without a nested container before the time budget is checked again,
+/// looking at the clock for every entry would slow down parsing
+pub const BUDGET_CHECK_INTERVAL: usize = 256;
+
+/// State shared by all nesting levels of one parse
+struct ParseState<'a> {
+    /// The options of the parse
+    options: &'a ParseOptions,
+    /// When to give up, from [`ParseOptions::time_budget`]
+    deadline: Option<Instant>,
+    /// Number of entries since the time budget was last checked
+    unchecked_entries: usize,
+    /// The offset of the last KLV header read, for [`InternalError::offset_hint`]
+    last_header: Option<u64>,
+    /// Number of values decoded so far
+    total_values: usize,
+    /// Whether errors end the current container instead of the parse, see [`parse_gpmf_lossy`]
+    lossy: bool,
+    /// The problems recovered from
+    warnings: Vec<GpmfWarning>,
+    /// The numbered tags of the containers being parsed, e.g. `STRM[3]`
+    path: Vec<String>,
+}
+
+impl<'a> ParseState<'a> {
+    /// Start a parse with the given options
+    fn new(options: &'a ParseOptions, lossy: bool) -> Self {
+        Self {
+            options,
+            //a budget too large to represent is the same as no budget
+            deadline: options
+                .time_budget
+                .and_then(|budget| Instant::now().checked_add(budget)),
+            unchecked_entries: 0,
+            last_header: None,
+            total_values: 0,
+            lossy,
+            warnings: Vec::new(),
+            path: Vec::new(),
+        }
+    }
+
+    /// Whether the time budget ran out before an entry, checked for nested containers
+    /// and every [`BUDGET_CHECK_INTERVAL`] entries
+    fn out_of_time(&mut self, nested: bool) -> bool {
+        let Some(deadline) = self.deadline else {
+            return false;
+        };
+        if !nested && self.unchecked_entries < BUDGET_CHECK_INTERVAL {
+            self.unchecked_entries += 1;
+            return false;
+        }
+        self.unchecked_entries = 0;
+        Instant::now() >= deadline
+    }
+
+    /// Fail with a [`LimitExceeded`] if `value` is above the `max` of `limit`
+    fn check(&self, limit: Limit, max: usize, value: usize, offset: u64) -> Result<(), GpmfError> {
+        if value > max {
+            warn!("{} limit of {} exceeded at offset {}", limit, max, offset);
+            return Err(GpmfError::LimitExceeded(LimitExceeded {
+                limit,
+                max,
+                offset,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Add `count` decoded values to the total, failing with a [`LimitExceeded`] if it is above the limit.
+    /// A count or total too large for `usize`, possible on 32 bit targets, exceeds the limit too
+    fn count_values(&mut self, count: Option<usize>, offset: u64) -> Result<(), GpmfError> {
+        let max = self.options.limits.max_total_values;
+        let Some(total) = count.and_then(|count| self.total_values.checked_add(count)) else {
+            warn!("Number of values overflows at offset {}", offset);
+            return Err(GpmfError::LimitExceeded(LimitExceeded {
+                limit: Limit::TotalValues,
+                max,
+                offset,
+            }));
+        };
+        self.total_values = total;
+        self.check(Limit::TotalValues, max, total, offset)
+    }
+
+    /// Wrap an error inside the entry with `tag` at `offset` in an [`ErrorContext`] with the current path.
+    /// Running out of time is not wrapped, it is merged into the parents as it propagates
+    fn in_entry(&self, error: GpmfError, tag: Option<&Tag>, offset: u64) -> GpmfError {
+        if matches!(
+            error,
+            GpmfError::Context(_) | GpmfError::BudgetExceeded(_) | GpmfError::Internal(_)
+        ) {
+            return error;
+        }
+        let mut path = self.path.join("/");
+        if let Some(tag) = tag {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(&path_segment(tag));
+        }
+        GpmfError::Context(ErrorContext {
+            path,
+            offset,
+            error: Box::new(error),
+        })
+    }
+
+    /// With [`Strictness::Lenient`] record `error` in the entry with `tag` as a warning for the `data` bytes kept raw,
+    /// otherwise fail with it
+    fn tolerate(&mut self, error: GpmfError, tag: &Tag, data: Range<u64>) -> Result<(), GpmfError> {
+        let error = self.in_entry(error, Some(tag), data.start - 8);
+        if self.options.strictness != Strictness::Lenient {
+            return Err(error);
+        }
+        warn!("{}, keeping {} raw bytes", error, data.end - data.start);
+        self.warnings.push(GpmfWarning {
+            error,
+            skipped: data,
+        });
+        Ok(())
+    }
+}
+
+/// The FourCC of `tag` in an [`ErrorContext`] path, with unsupported tags escaped like their [`Display`](fmt::Display)
+fn path_segment(tag: &Tag) -> String {
+    match tag {
+        Tag::Other(_) => tag.to_string(),
+        tag => tag.fourcc(),
+    }
+}
+
+/// Parse the type letters of a TYPE definition, up to the first NUL.
+/// A letter followed by a bracketed count like `f[4]` is expanded into that many elements,
+/// up to the 255 elements that fit the one byte structure size.
+/// `offset` is the position of the definition in the top level buffer, used for error reporting
+fn parse_type_def(def: &[u8], offset: u64) -> Result<Vec<Type>, GpmfError> {
+    let def = &def[..def.iter().position(|b| *b == 0).unwrap_or(def.len())];
+    let mut types: Vec<Type> = Vec::new();
+    let mut i = 0;
+    while i < def.len() {
+        let type_u8 = def[i];
+        if type_u8 == b'[' {
+            let len = def[i..].iter().position(|b| *b == b']').ok_or_else(|| {
+                GpmfError::InvalidTypeDef {
+                    message: "Unterminated array count".to_string(),
+                    offset: offset + i as u64,
+                }
+            })?;
+            let count = std::str::from_utf8(&def[i + 1..i + len])
+                .ok()
+                .and_then(|count| count.parse::<u64>().ok())
+                .filter(|count| *count > 0)
+                .ok_or_else(|| GpmfError::InvalidTypeDef {
+                    message: format!(
+                        "Invalid array count {:?}",
+                        String::from_utf8_lossy(&def[i..i + len + 1])
+                    ),
+                    offset: offset + i as u64,
+                })?;
+            let typ = *types.last().ok_or_else(|| GpmfError::InvalidTypeDef {
+                message: "Array count without a type".to_string(),
+                offset: offset + i as u64,
+            })?;
+            if (types.len() as u64)
+                .checked_add(count - 1)
+                .is_none_or(|total| total > u8::MAX as u64)
+            {
+                return Err(GpmfError::InvalidTypeDef {
+                    message: format!(
+                        "Array count {} makes the structure longer than {} elements",
+                        count,
+                        u8::MAX
+                    ),
+                    offset: offset + i as u64,
+                });
+            }
+            //the type itself was already pushed once
+            types.extend(std::iter::repeat_n(typ, count as usize - 1));
+            i += len + 1;
+            continue;
+        }
+        let typ = Type::try_from(type_u8).map_err(|_| GpmfError::UnknownType {
+            type_u8,
+            offset: offset + i as u64,
+        })?;
+        types.push(typ);
+        i += 1;
+    }
+    Ok(types)
+}
+
+/// An error inside an entry, with the path of tags leading to it
+#[derive(Debug)]
+pub struct ErrorContext {
+    /// The tags of the containers and the entry, e.g. `DEVC[0]/STRM[3]/GPS5`.
+    /// Each container is numbered among its siblings with the same tag
+    pub path: String,
+    /// The offset of the entry
+    pub offset: u64,
+    /// The error
+    pub error: Box<GpmfError>,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} @ offset 0x{:X}: {}",
+            self.path, self.offset, self.error
+        )
+    }
+}
+
+impl std::error::Error for ErrorContext {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+/// Error returned by [`parse_gpmf_catching`] when the parser panicked
+#[derive(Debug)]
+pub struct InternalError {
+    /// The panic message
+    pub message: String,
+    /// The byte offset of the last KLV header read before the panic, `None` if there was none
+    pub offset_hint: Option<u64>,
+}
+
+impl fmt::Display for InternalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Internal parser error, please file a bug report: {}",
+            self.message
+        )?;
+        if let Some(offset) = self.offset_hint {
+            write!(f, " (after the entry at offset {})", offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InternalError {}
+
+/// Errors returned by the parser, each carrying the byte offset in the input where it happened
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum GpmfError {
+    /// The input ended in the middle of an entry, e.g. a file that is still being written
+    UnexpectedEof {
+        /// The offset of the read that ran out of data
+        offset: u64,
+    },
+    /// Reading the input failed
+    Io {
+        /// The underlying error
+        source: io::Error,
+        /// The offset of the failed read
+        offset: u64,
+    },
+    /// A KLV key with bytes that are not printable ASCII
+    InvalidFourCC {
+        /// The raw bytes of the key
+        fourcc: [u8; 4],
+        /// The offset of the key
+        offset: u64,
+    },
+    /// An unknown type letter in a TYPE definition, or in an entry header with [`Strictness::Strict`]
+    UnknownType {
+        /// The type letter
+        type_u8: u8,
+        /// The offset of the letter
+        offset: u64,
+    },
+    /// A tag that is not a known [`Tag`], with [`UnknownTags::Reject`]
+    UnknownTag {
+        /// The tag
+        tag: Tag,
+        /// The offset of the entry
+        offset: u64,
+    },
+    /// A malformed TYPE definition, e.g. an unterminated array count
+    InvalidTypeDef {
+        /// What is wrong with the definition
+        message: String,
+        /// The offset of the problem within the definition
+        offset: u64,
+    },
+    /// Complex data without a TYPE definition in its stream
+    MissingTypeDef {
+        /// The tag of the complex entry
+        tag: Tag,
+        /// The offset of the entry
+        offset: u64,
+    },
+    /// A structure size that is not a multiple of the size of its type
+    InvalidSize {
+        /// The tag of the entry
+        tag: Tag,
+        /// The structure size from the header
+        size: u8,
+        /// The type of the entry
+        typ: Type,
+        /// The size of the type, or of the TYPE definition for complex entries
+        type_size: usize,
+        /// The offset of the entry
+        offset: u64,
+    },
+    /// A nested container declaring more bytes than remain in its parent
+    NestedOverrun {
+        /// The tag of the container
+        tag: Tag,
+        /// The number of bytes the header declares
+        declared: usize,
+        /// The number of bytes remaining in the parent
+        remaining: usize,
+        /// The offset of the container
+        offset: u64,
+    },
+    /// An entry declaring more data bytes than remain in the input, e.g. a file that is still being written
+    EntryOverrun {
+        /// The tag of the entry
+        tag: Tag,
+        /// The number of data bytes the header declares
+        declared: usize,
+        /// The number of bytes remaining after the header
+        remaining: usize,
+        /// The offset of the entry
+        offset: u64,
+    },
+    /// A top level container declaring no bytes but followed by more data, which is parsed as its children
+    /// like the reference parser does. Only reported as a [`GpmfWarning`]
+    EmptyContainer {
+        /// The tag of the container
+        tag: Tag,
+        /// The number of bytes after the header parsed as its children
+        extended: usize,
+        /// The offset of the container
+        offset: u64,
+    },
+    /// An entry at the end of the input or its container without all of its padding bytes,
+    /// e.g. trimmed by an extraction tool. Only reported as a [`GpmfWarning`]
+    MissingPadding {
+        /// The tag of the entry
+        tag: Tag,
+        /// The number of padding bytes that are missing
+        missing: usize,
+        /// The offset of the entry
+        offset: u64,
+    },
+    /// A type that can only be read as a whole entry, not as a single element
+    UnreadableType {
+        /// The type
+        typ: Type,
+        /// The offset of the element
+        offset: u64,
+    },
+    /// The time budget ran out, see [`BudgetExceeded`]
+    BudgetExceeded(BudgetExceeded),
+    /// The input exceeds one of the limits, see [`LimitExceeded`]
+    LimitExceeded(LimitExceeded),
+    /// The parser panicked, see [`InternalError`]
+    Internal(InternalError),
+    /// An error inside an entry, see [`ErrorContext`]
+    Context(ErrorContext),
+}
+
+impl GpmfError {
+    /// Wrap an I/O error at `offset`, telling a truncated input apart from other failures
+    fn io(source: io::Error, offset: u64) -> Self {
+        if source.kind() == io::ErrorKind::UnexpectedEof {
+            GpmfError::UnexpectedEof { offset }
+        } else {
+            GpmfError::Io { source, offset }
+        }
+    }
+
+    /// The byte offset in the input where the error happened, if known
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            GpmfError::UnexpectedEof { offset }
+            | GpmfError::Io { offset, .. }
+            | GpmfError::InvalidFourCC { offset, .. }
+            | GpmfError::UnknownType { offset, .. }
+            | GpmfError::UnknownTag { offset, .. }
+            | GpmfError::InvalidTypeDef { offset, .. }
+            | GpmfError::MissingTypeDef { offset, .. }
+            | GpmfError::InvalidSize { offset, .. }
+            | GpmfError::NestedOverrun { offset, .. }
+            | GpmfError::EntryOverrun { offset, .. }
+            | GpmfError::EmptyContainer { offset, .. }
+            | GpmfError::MissingPadding { offset, .. }
+            | GpmfError::UnreadableType { offset, .. } => Some(*offset),
+            GpmfError::BudgetExceeded(exceeded) => Some(exceeded.offset),
+            GpmfError::LimitExceeded(exceeded) => Some(exceeded.offset),
+            GpmfError::Internal(internal) => internal.offset_hint,
+            GpmfError::Context(context) => context.error.offset(),
+        }
+    }
+
+    /// The error without the [`ErrorContext`] it happened in
+    pub fn inner(&self) -> &GpmfError {
+        match self {
+            GpmfError::Context(context) => context.error.inner(),
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for GpmfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpmfError::UnexpectedEof { offset } => {
+                write!(f, "Unexpected end of input at offset {}", offset)
+            }
+            GpmfError::Io { source, offset } => write!(f, "{} at offset {}", source, offset),
+            GpmfError::InvalidFourCC { fourcc, offset } => write!(
+                f,
+                "Invalid FourCC \"{}\" at offset {}",
+                fourcc.escape_ascii(),
+                offset
+            ),
+            GpmfError::UnknownType { type_u8, offset } => write!(
+                f,
+                "Unknown type {:?} (0x{:02X}) at offset {}",
+                *type_u8 as char, type_u8, offset
+            ),
+            GpmfError::UnknownTag { tag, offset } => {
+                write!(f, "Unknown tag {} at offset {}", tag, offset)
+            }
+            GpmfError::InvalidTypeDef { message, offset } => {
+                write!(f, "{} in TYPE definition at offset {}", message, offset)
+            }
+            GpmfError::MissingTypeDef { tag, offset } => write!(
+                f,
+                "Complex {} at offset {} has no TYPE definition in its stream",
+                tag.fourcc(),
+                offset
+            ),
+            GpmfError::InvalidSize {
+                tag,
+                size,
+                typ,
+                type_size,
+                offset,
+            } => write!(
+                f,
+                "{} at offset {} has a structure size of {} bytes which is not a multiple of the {} byte size of type {}",
+                tag.fourcc(),
+                offset,
+                size,
+                type_size,
+                typ
+            ),
+            GpmfError::NestedOverrun {
+                tag,
+                declared,
+                remaining,
+                offset,
+            } => write!(
+                f,
+                "Nested {} at offset {} declares {} bytes but only {} remain",
+                tag.fourcc(),
+                offset,
+                declared,
+                remaining
+            ),
+            GpmfError::EntryOverrun {
+                tag,
+                declared,
+                remaining,
+                offset,
+            } => write!(
+                f,
+                "{} at offset {} declares {} data bytes but only {} remain",
+                tag.fourcc(),
+                offset,
+                declared,
+                remaining
+            ),
+            GpmfError::EmptyContainer {
+                tag,
+                extended,
+                offset,
+            } => write!(
+                f,
+                "Empty {} at offset {}, parsing the remaining {} bytes as its children",
+                tag.fourcc(),
+                offset,
+                extended
+            ),
+            GpmfError::MissingPadding {
+                tag,
+                missing,
+                offset,
+            } => write!(
+                f,
+                "{} at offset {} is missing {} padding bytes",
+                tag.fourcc(),
+                offset,
+                missing
+            ),
+            GpmfError::UnreadableType { typ, offset } => write!(
+                f,
+                "Type {} can not be read as a single element at offset {}",
+                typ, offset
+            ),
+            GpmfError::BudgetExceeded(exceeded) => exceeded.fmt(f),
+            GpmfError::LimitExceeded(exceeded) => exceeded.fmt(f),
+            GpmfError::Internal(internal) => internal.fmt(f),
+            GpmfError::Context(context) => context.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for GpmfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GpmfError::Io { source, .. } => Some(source),
+            GpmfError::Context(context) => Some(&*context.error),
+            _ => None,
+        }
     }
-    Ok(tag)
 }
 
 /// Parse the GPMF stream using the bytorder crate
-/// This function will be called recursively to handle nested data structures
-pub fn parse_gpmf(input: &[u8]) -> anyhow::Result<Vec<KeyValue>> {
-    //the complex data structure types
-    let mut type_def: Option<Vec<Type>> = None;
+///
+/// # Errors
+///
+/// Returns an error if the input is not a valid GPMF stream or exceeds the default [`ParseLimits`]
+pub fn parse_gpmf(input: &[u8]) -> Result<Vec<KeyValue>, GpmfError> {
+    parse_gpmf_opts(input, &ParseOptions::default())
+}
+
+/// Parse the GPMF stream like [`parse_gpmf`] with custom [`ParseLimits`]
+///
+/// # Errors
+///
+/// Returns an error if the input is not a valid GPMF stream, or [`GpmfError::LimitExceeded`] if it exceeds one of the limits
+pub fn parse_gpmf_with_limits(
+    input: &[u8],
+    limits: ParseLimits,
+) -> Result<Vec<KeyValue>, GpmfError> {
+    parse_gpmf_opts(
+        input,
+        &ParseOptions {
+            limits,
+            ..Default::default()
+        },
+    )
+}
 
+/// Parse the GPMF stream like [`parse_gpmf`], converting any panic inside the parser into an error
+///
+/// The parser should never panic, this is a safety net for servers embedding it until every panicking path is gone.
+/// The default panic hook still reports the panic.
+///
+/// # Errors
+///
+/// Returns an error if the input is not a valid GPMF stream, or [`GpmfError::Internal`] if the parser panicked
+pub fn parse_gpmf_catching(input: &[u8]) -> Result<Vec<KeyValue>, GpmfError> {
+    parse_catching(input, &ParseOptions::default(), |input, state| {
+        parse_entries(input, 0, 0, state)
+    })
+}
+
+/// Run `parse` with a new state, converting a panic into [`GpmfError::Internal`]
+/// with the offset of the last header read
+fn parse_catching(
+    input: &[u8],
+    options: &ParseOptions,
+    parse: impl FnOnce(&[u8], &mut ParseState) -> Result<Vec<KeyValue>, GpmfError>,
+) -> Result<Vec<KeyValue>, GpmfError> {
+    let mut state = ParseState::new(options, false);
+    // after a panic only the offset is read from the state, which is valid at any point
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| parse(input, &mut state)));
+    result.unwrap_or_else(|panic| {
+        let message = if let Some(message) = panic.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = panic.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic".to_string()
+        };
+        error!(
+            "Parser panicked after the entry at offset {:?}: {}",
+            state.last_header, message
+        );
+        Err(GpmfError::Internal(InternalError {
+            message,
+            offset_hint: state.last_header,
+        }))
+    })
+}
+
+/// A problem [`parse_gpmf_lossy`] recovered from
+#[derive(Debug)]
+pub struct GpmfWarning {
+    /// What went wrong
+    pub error: GpmfError,
+    /// The input bytes that were not parsed because of it, from the error to the end of its container.
+    /// Empty for a truncated container, whose remaining children are still parsed, for missing padding
+    /// and for an empty top level container extended to the end of the input.
+    /// With [`Strictness::Lenient`] the data of an entry kept as [`Value::Raw`]
+    pub skipped: Range<u64>,
+}
+
+impl fmt::Display for GpmfWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, skipped bytes {}..{}",
+            self.error, self.skipped.start, self.skipped.end
+        )
+    }
+}
+
+/// Parse the GPMF stream like [`parse_gpmf`], keeping everything that could be parsed
+///
+/// An error inside a container ends that container but not its parent, so a corrupt STRM
+/// keeps its siblings, and a container cut short by the end of the input keeps the children that are present.
+/// Every problem is reported as a [`GpmfWarning`].
+pub fn parse_gpmf_lossy(input: &[u8]) -> (Vec<KeyValue>, Vec<GpmfWarning>) {
+    let options = ParseOptions::default();
+    let mut state = ParseState::new(&options, true);
+    let res = parse_entries(input, 0, 0, &mut state).unwrap_or_default();
+    (res, state.warnings)
+}
+
+/// Parse the GPMF stream using the given [`ParseOptions`]
+///
+/// # Errors
+///
+/// Returns an error if the input is not a valid GPMF stream.
+/// If the time budget runs out the error is [`GpmfError::BudgetExceeded`] holding the partial results,
+/// if the input exceeds one of the limits it is [`GpmfError::LimitExceeded`].
+pub fn parse_gpmf_opts(input: &[u8], options: &ParseOptions) -> Result<Vec<KeyValue>, GpmfError> {
+    parse_gpmf_with_warnings(input, options).map(|(res, _)| res)
+}
+
+/// Parse the GPMF stream like [`parse_gpmf_opts`], also returning the problems that were recovered from,
+/// e.g. the bytes skipped by [`ParseOptions::resync`]
+///
+/// # Errors
+///
+/// Returns the same errors as [`parse_gpmf_opts`]
+pub fn parse_gpmf_with_warnings(
+    input: &[u8],
+    options: &ParseOptions,
+) -> Result<(Vec<KeyValue>, Vec<GpmfWarning>), GpmfError> {
+    let mut state = ParseState::new(options, false);
+    let res = parse_entries(input, 0, 0, &mut state)?;
+    Ok((res, state.warnings))
+}
+
+/// Parse the KLV entries of one nesting level.
+/// This function will be called recursively to handle nested data structures,
+/// `base_offset` is the position of `input` within the top level buffer and `depth` the nesting level of its entries.
+///
+/// With [`ParseOptions::resync`] parsing continues at the next DEVC or STRM after an error,
+/// when parsing lossy an error only ends the current level, the entries before it are kept
+fn parse_entries(
+    input: &[u8],
+    base_offset: u64,
+    depth: usize,
+    state: &mut ParseState,
+) -> Result<Vec<KeyValue>, GpmfError> {
     let mut res = Vec::new();
+    //the complex data structure types, set by each TYPE for the Complex entries after it.
+    //a TYPE only applies to the container it appears in so each level starts without one,
+    //and it is kept when parsing resyncs within the level
+    let mut type_def = None;
+    //where parsing (re)started within input
+    let mut start = 0;
+    loop {
+        let mut entry_offset = base_offset + start as u64;
+        let Err(error) = parse_entries_into(
+            &mut res,
+            &input[start..],
+            base_offset + start as u64,
+            depth,
+            state,
+            &mut type_def,
+            &mut entry_offset,
+        ) else {
+            return Ok(res);
+        };
+        // the tag of the failed entry, unless its header is cut short
+        let rel = (entry_offset - base_offset) as usize;
+        let tag = input
+            .get(rel..rel + 4)
+            .and_then(|fourcc| fourcc.try_into().ok())
+            .map(Tag::from_fourcc);
+        let error = state.in_entry(error, tag.as_ref(), entry_offset);
+
+        let recoverable = !matches!(
+            error.inner(),
+            GpmfError::BudgetExceeded(_) | GpmfError::LimitExceeded(_)
+        );
+        if state.options.resync && recoverable {
+            // entries are 32 bit aligned, the failed one starts at least 4 bytes before the next
+            let from = rel + 4;
+            if let Some(next) = find_resync(input, from) {
+                let skipped = entry_offset..base_offset + next as u64;
+                warn!(
+                    "Skipping bytes {}..{} to resync after error: {}",
+                    skipped.start, skipped.end, error
+                );
+                state.warnings.push(GpmfWarning { error, skipped });
+                start = next;
+                continue;
+            }
+        }
+
+        if !state.lossy {
+            return Err(error);
+        }
+        let end = base_offset + input.len() as u64;
+        let skip_from = error.offset().unwrap_or(base_offset).min(end);
+        warn!(
+            "Skipping bytes {}..{} after error: {}",
+            skip_from, end, error
+        );
+        state.warnings.push(GpmfWarning {
+            error,
+            skipped: skip_from..end,
+        });
+        return Ok(res);
+    }
+}
 
+/// Find the next plausible DEVC or STRM header in `input` at a 32 bit aligned position from `from`
+fn find_resync(input: &[u8], from: usize) -> Option<usize> {
+    (from..input.len().saturating_sub(7))
+        .step_by(4)
+        .find(|&pos| {
+            let header = &input[pos..pos + 8];
+            let len = header[5] as usize * u16::from_be_bytes([header[6], header[7]]) as usize;
+            matches!(&header[..4], b"DEVC" | b"STRM")
+                && header[4] == 0
+                && len > 0
+                && (pos + 8)
+                    .checked_add(len)
+                    .is_some_and(|end| end <= input.len())
+        })
+}
+
+/// Parse the KLV entries of one nesting level into `res`, see [`parse_entries`].
+/// `type_def` is the TYPE in effect for Complex entries and
+/// `last_entry` is updated with the position of each entry before it is parsed
+fn parse_entries_into(
+    res: &mut Vec<KeyValue>,
+    input: &[u8],
+    base_offset: u64,
+    depth: usize,
+    state: &mut ParseState,
+    type_def: &mut Option<Vec<Type>>,
+    last_entry: &mut u64,
+) -> Result<(), GpmfError> {
     //the cursor to handle reading from the slice
     let mut input = Cursor::new(input);
 
-    while input.has_data_left()? {
-        let tag = read_tag(&mut input)?;
-        let type_u8 = input.read_u8()?;
+    while read_at(&mut input, base_offset, |input| input.has_data_left())? {
+        //the position of the entry in the top level buffer
+        let entry_offset = base_offset + input.position();
+        *last_entry = entry_offset;
+        state.last_header = Some(entry_offset);
+        let tag = read_key(&mut input, base_offset)?;
+        let type_u8 = read_at(&mut input, base_offset, |input| input.read_u8())?;
         debug!("Type_u8 {}", type_u8);
 
-        let typ = Type::try_from(type_u8)?;
-        debug!("Type {}\t{}\t{}", type_u8, type_u8 as char, typ);
-
-        let size = input.read_u8()?;
-        let repeat = input.read_u16::<BigEndian>()?;
+        let size = read_at(&mut input, base_offset, |input| input.read_u8())?;
+        let repeat = read_at(&mut input, base_offset, |input| {
+            input.read_u16::<BigEndian>()
+        })?;
         debug!("Type Size {} bytes Repeat {}", size, repeat);
 
-        let num_bytes = size as usize * repeat as usize;
+        let header = Header {
+            type_u8,
+            size,
+            repeat,
+        };
+        if state.out_of_time(type_u8 == Type::Nested as u8) {
+            warn!("Parse time budget exceeded at offset {}", entry_offset);
+            return Err(GpmfError::BudgetExceeded(BudgetExceeded {
+                partial: std::mem::take(res),
+                offset: entry_offset,
+            }));
+        }
+        let num_bytes = header.data_len();
+        state.check(
+            Limit::PayloadBytes,
+            state.options.limits.max_payload_bytes,
+            num_bytes,
+            entry_offset,
+        )?;
 
-        let type_size = if typ == Type::Complex {
-            type_def.as_ref().unwrap().iter().map(|t| t.size()).sum()
-        } else {
-            typ.size()
+        // fail before reading or allocating anything for data that is not there,
+        // nested containers are checked against their parent instead
+        let remaining = usize::try_from(input.position())
+            .ok()
+            .and_then(|position| input.get_ref().len().checked_sub(position))
+            .unwrap_or(0);
+        if type_u8 != Type::Nested as u8 && num_bytes > remaining {
+            return Err(GpmfError::EntryOverrun {
+                tag,
+                declared: num_bytes,
+                remaining,
+                offset: entry_offset,
+            });
+        }
+
+        if let Tag::Other(_) = tag {
+            match state.options.unknown_tags {
+                UnknownTags::Keep => {}
+                UnknownTags::Skip => {
+                    debug!("Skipping unknown {} at offset {}", tag, entry_offset);
+                    read_at(&mut input, base_offset, |input| {
+                        let skipped =
+                            io::copy(&mut input.by_ref().take(num_bytes as u64), &mut io::sink())?;
+                        if skipped < num_bytes as u64 {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        Ok(())
+                    })?;
+                    skip_padding(
+                        &mut input,
+                        base_offset,
+                        num_bytes,
+                        &tag,
+                        state,
+                        entry_offset,
+                    )?;
+                    continue;
+                }
+                UnknownTags::Reject => {
+                    return Err(GpmfError::UnknownTag {
+                        tag,
+                        offset: entry_offset,
+                    })
+                }
+            }
+        }
+
+        // the data of the entry in the top level buffer
+        let data = entry_offset + 8..entry_offset + 8 + num_bytes as u64;
+        let data_start = input.position();
+
+        let Ok(typ) = Type::try_from(type_u8) else {
+            if state.options.strictness == Strictness::Strict {
+                return Err(GpmfError::UnknownType {
+                    type_u8,
+                    offset: entry_offset + 4,
+                });
+            }
+            warn!(
+                "Unknown type {:?} (0x{:02X}) for {} at offset {}, keeping {} raw bytes",
+                type_u8 as char, type_u8, tag, entry_offset, num_bytes
+            );
+            push_raw(
+                res,
+                &mut input,
+                base_offset,
+                tag,
+                header,
+                state,
+                entry_offset,
+            )?;
+            continue;
+        };
+        debug!("Type {}\t{}\t{}", type_u8, type_u8 as char, typ);
+
+        // entries without samples carry no data and no padding
+        if num_bytes == 0 && typ != Type::Nested {
+            debug!("{:?} at offset {} has no samples", tag, entry_offset);
+            res.push(KeyValue {
+                key: tag,
+                value: Value::Empty(typ),
+                header: Some(header),
+                raw: None,
+                span: Some(span(entry_offset, base_offset + input.position())),
+                padding: None,
+            });
+            continue;
+        }
+
+        let type_size = match (typ, &*type_def) {
+            // at most 255 elements of at most 16 bytes, see parse_type_def
+            (Type::Complex, Some(type_def)) => type_def.iter().map(|t| t.size()).sum(),
+            (Type::Complex, None) => {
+                let error = GpmfError::MissingTypeDef {
+                    tag: tag.clone(),
+                    offset: entry_offset,
+                };
+                state.tolerate(error, &tag, data)?;
+                push_raw(
+                    res,
+                    &mut input,
+                    base_offset,
+                    tag,
+                    header,
+                    state,
+                    entry_offset,
+                )?;
+                continue;
+            }
+            (
+                Type::I8
+                | Type::U8
+                | Type::Char
+                | Type::F64
+                | Type::F32
+                | Type::FourCC
+                | Type::U128
+                | Type::I64
+                | Type::U64
+                | Type::I32
+                | Type::U32
+                | Type::Fixed32
+                | Type::Fixed64
+                | Type::I16
+                | Type::U16
+                | Type::Date
+                | Type::Nested,
+                _,
+            ) => typ.size(),
         };
 
-        let num_elements = if type_size != 0 {
-            size as usize / type_size
-        } else {
+        if (size as usize)
+            .checked_rem(type_size)
+            .is_some_and(|rem| rem != 0)
+        {
+            let error = GpmfError::InvalidSize {
+                tag: tag.clone(),
+                size,
+                typ,
+                type_size,
+                offset: entry_offset,
+            };
+            state.tolerate(error, &tag, data)?;
+            push_raw(
+                res,
+                &mut input,
+                base_offset,
+                tag,
+                header,
+                state,
+                entry_offset,
+            )?;
+            continue;
+        }
+
+        let num_elements = (size as usize).checked_div(type_size).unwrap_or_else(|| {
             error!("Type size is Zero - Trying to continue assuming zero elements");
             0
-        };
+        });
         debug!(
             "Type Calc Size {} bytes Num Elements {}",
             type_size, num_elements
         );
 
-        let mod4 = num_bytes % 4;
-        let padding_bytes = if mod4 == 0 { 0 } else { 4 - mod4 };
-        trace!(
-            "Num Bytes {} Mod4 {} Padding Bytes {}",
-            num_bytes,
-            mod4,
-            padding_bytes
-        );
+        let num_values = match typ {
+            Type::Nested => Some(0),
+            Type::Char => {
+                // a single string of repeat characters, or repeat strings of num_elements characters
+                let (string_len, num_strings) = if num_elements == 1 {
+                    (repeat as usize, 1)
+                } else {
+                    (num_elements, repeat as usize)
+                };
+                state.check(
+                    Limit::StringLen,
+                    state.options.limits.max_string_len,
+                    string_len,
+                    entry_offset,
+                )?;
+                Some(num_strings)
+            }
+            Type::Complex => (repeat as usize).checked_mul(type_def.as_ref().map_or(0, Vec::len)),
+            Type::I8
+            | Type::U8
+            | Type::F64
+            | Type::F32
+            | Type::FourCC
+            | Type::U128
+            | Type::I64
+            | Type::U64
+            | Type::I32
+            | Type::U32
+            | Type::Fixed32
+            | Type::Fixed64
+            | Type::I16
+            | Type::U16
+            | Type::Date => (repeat as usize).checked_mul(num_elements),
+        };
+        state.count_values(num_values, entry_offset)?;
 
+        // the fixed width fields of strings and TYPE definitions as they were read
+        let mut raw = None;
         let value = match typ {
             Type::Char => {
                 if num_elements == 1 {
                     // special case for repeat of 1 element
-                    let mut vec = Vec::new();
-                    let _take = input.by_ref().take(repeat as u64).read_to_end(&mut vec)?;
-
-                    if tag != Tag::TYPE {
-                        let v: String = vec
-                            .into_iter()
-                            .take_while(|b| *b != 0)
-                            .map(|b| b as char)
-                            .collect();
+                    let mut vec = vec![0u8; repeat as usize];
+                    read_at(&mut input, base_offset, |input| input.read_exact(&mut vec))?;
+
+                    // some firmwares write GPSU as a string, decode it like a **U** date when it is one
+                    let gpsu_date = match <[u8; 16]>::try_from(vec.as_slice()) {
+                        Ok(buf) if tag == Tag::GPSU => match decode_date(buf) {
+                            Value::RawDate(_) => None,
+                            #[cfg(not(feature = "dates"))]
+                            Value::Date(_) if !is_date_shaped(&buf) => None,
+                            date => Some(date),
+                        },
+                        _ => None,
+                    };
+                    if let Some(date) = gpsu_date {
+                        debug!("GPSU string as date {:?}", date);
+                        Value::Simple(vec![vec![date]])
+                    } else if tag != Tag::TYPE {
+                        let v = decode_string(&vec);
                         debug!("char/string {:?}", v);
+                        raw = Some(vec);
                         Value::String(v)
                     } else {
-                        let v: Vec<_> = vec
-                            .into_iter()
-                            .take_while(|b| *b != 0)
-                            .map(|type_u8| Type::try_from(type_u8).unwrap())
-                            .collect();
+                        let v = parse_type_def(&vec, entry_offset + 8)?;
                         info!("TYPE def types {:?}", v);
-                        type_def = Some(v.clone());
+                        *type_def = Some(v.clone());
+                        // keep array counts like f[4] for writing
+                        raw = Some(vec);
                         Value::Type(v)
                     }
                 } else {
                     let mut seq = Vec::new();
+                    let mut fields = Vec::with_capacity(num_bytes);
                     for i in 0..repeat {
-                        let mut vec = Vec::new();
-                        let _take = input
-                            .by_ref()
-                            .take(num_elements as u64)
-                            .read_to_end(&mut vec)?;
+                        let mut vec = vec![0u8; num_elements];
+                        read_at(&mut input, base_offset, |input| input.read_exact(&mut vec))?;
 
                         if enabled!(Level::TRACE) {
                             vec.iter()
@@ -161,27 +1344,33 @@ pub fn parse_gpmf(input: &[u8]) -> anyhow::Result<Vec<KeyValue>> {
                                 .for_each(|(i, c)| trace!("{}: {} '{}'", i, c, *c as char));
                         }
 
-                        let v: String = vec
-                            .into_iter()
-                            .take_while(|b| *b != 0)
-                            .map(|b| b as char)
-                            .collect();
+                        let v = decode_string(&vec);
                         debug!("{}: char/string {:?}", i, v);
                         seq.push(v);
+                        fields.extend_from_slice(&vec);
                     }
+                    raw = Some(fields);
                     Value::Strings(seq)
                 }
             }
             Type::Complex => {
-                let type_def = type_def
-                    .as_ref()
-                    .ok_or(anyhow::Error::msg("TYPE must be set"))?;
+                let type_def = type_def.as_ref().ok_or_else(|| GpmfError::MissingTypeDef {
+                    tag: tag.clone(),
+                    offset: entry_offset,
+                })?;
                 //TODO assert_eq!(num_elements,type_def.len());
                 let mut seq = Vec::new();
                 for i in 0..repeat {
                     let mut complex = Vec::new();
-                    for t in type_def {
-                        let v = t.read(&mut input)?;
+                    // a run of characters like c[4] is one fixed length string
+                    for field in type_def.chunk_by(|a, b| *a == Type::Char && *b == Type::Char) {
+                        let v = if field[0] == Type::Char {
+                            let mut vec = vec![0u8; field.len()];
+                            read_at(&mut input, base_offset, |input| input.read_exact(&mut vec))?;
+                            Value::String(decode_string(&vec))
+                        } else {
+                            field[0].read(&mut input, base_offset)?
+                        };
                         complex.push(v);
                     }
                     info!("{}: Complex Type {:?}", i, complex);
@@ -190,14 +1379,84 @@ pub fn parse_gpmf(input: &[u8]) -> anyhow::Result<Vec<KeyValue>> {
                 Value::Complex(seq)
             }
             Type::Nested => {
+                state.check(
+                    Limit::Nesting,
+                    state.options.limits.max_nesting,
+                    depth + 1,
+                    entry_offset,
+                )?;
                 let offset = input.position();
-                let len = num_bytes;
+                let remaining = &input.get_ref()[offset as usize..];
+
+                // like the reference parser, an empty top level container followed by more data extends to the end
+                let len = if depth == 0 && num_bytes == 0 && !remaining.is_empty() {
+                    let error = GpmfError::EmptyContainer {
+                        tag: tag.clone(),
+                        extended: remaining.len(),
+                        offset: entry_offset,
+                    };
+                    warn!("{}", error);
+                    let start = base_offset + offset;
+                    state.warnings.push(GpmfWarning {
+                        error: state.in_entry(error, Some(&tag), entry_offset),
+                        skipped: start..start,
+                    });
+                    remaining.len()
+                } else {
+                    num_bytes
+                };
                 let _span_ =
                     span!(Level::DEBUG, "Type::Nested", offset = offset, len = len).entered();
 
-                let next = &input.remaining_slice()[..num_bytes];
+                let next = match remaining.get(..len) {
+                    Some(next) => next,
+                    None => {
+                        let error = GpmfError::NestedOverrun {
+                            tag: tag.clone(),
+                            declared: num_bytes,
+                            remaining: remaining.len(),
+                            offset: entry_offset,
+                        };
+                        // when parsing lossy the container is cut short instead, keeping the children that are there
+                        if !state.lossy {
+                            return Err(error);
+                        }
+                        warn!("{}, parsing what remains", error);
+                        let end = base_offset + input.get_ref().len() as u64;
+                        state.warnings.push(GpmfWarning {
+                            error: state.in_entry(error, Some(&tag), entry_offset),
+                            skipped: end..end,
+                        });
+                        remaining
+                    }
+                };
 
-                let nested = parse_gpmf(next)?;
+                let index = res.iter().filter(|kv| kv.key == tag).count();
+                state
+                    .path
+                    .push(format!("{}[{}]", path_segment(&tag), index));
+                let nested = parse_entries(next, base_offset + offset, depth + 1, state);
+                state.path.pop();
+                let nested = match nested {
+                    Ok(nested) => nested,
+                    Err(GpmfError::BudgetExceeded(mut exceeded)) => {
+                        // keep what was parsed so far, with the truncated container as the last entry
+                        let partial = std::mem::take(&mut exceeded.partial);
+                        res.push(KeyValue {
+                            key: tag,
+                            value: Value::Nested(partial),
+                            header: Some(header),
+                            raw: None,
+                            span: Some(span(entry_offset, base_offset + offset + len as u64)),
+                            padding: None,
+                        });
+                        exceeded.partial = std::mem::take(res);
+                        return Err(GpmfError::BudgetExceeded(exceeded));
+                    }
+                    Err(err) => return Err(err),
+                };
+                // the nested entries have been consumed, continue after the container
+                input.set_position(offset + next.len() as u64);
                 Value::Nested(nested)
             }
 
@@ -207,7 +1466,7 @@ pub fn parse_gpmf(input: &[u8]) -> anyhow::Result<Vec<KeyValue>> {
                 for i in 0..repeat {
                     let mut vec = Vec::new();
                     for _j in 0..num_elements {
-                        let v = t.read(&mut input)?;
+                        let v = t.read(&mut input, base_offset)?;
                         vec.push(v);
                     }
                     debug!("{}: {:?}", i, vec);
@@ -217,25 +1476,132 @@ pub fn parse_gpmf(input: &[u8]) -> anyhow::Result<Vec<KeyValue>> {
             }
         };
 
-        let key_value = KeyValue { key: tag, value };
+        let data_end = input.position();
+        if typ != Type::Nested {
+            raw = raw.or_else(|| exact_bytes(&input, data_start, state));
+        }
+        skip_padding(
+            &mut input,
+            base_offset,
+            num_bytes,
+            &tag,
+            state,
+            entry_offset,
+        )?;
+
+        let key_value = KeyValue {
+            key: tag,
+            value,
+            header: Some(header),
+            raw,
+            span: Some(span(entry_offset, base_offset + input.position())),
+            padding: exact_bytes(&input, data_end, state),
+        };
 
         res.push(key_value);
+    }
+    Ok(())
+}
+
+/// Keep an entry that is not decoded as a [`Value::Raw`] holding its data, and skip its padding
+fn push_raw(
+    res: &mut Vec<KeyValue>,
+    input: &mut Cursor<&[u8]>,
+    base_offset: u64,
+    tag: Tag,
+    header: Header,
+    state: &mut ParseState,
+    entry_offset: u64,
+) -> Result<(), GpmfError> {
+    state.count_values(Some(1), entry_offset)?;
+    let num_bytes = header.data_len();
+    let mut bytes = vec![0u8; num_bytes];
+    read_at(input, base_offset, |input| input.read_exact(&mut bytes))?;
+    let data_end = input.position();
+    skip_padding(input, base_offset, num_bytes, &tag, state, entry_offset)?;
+    res.push(KeyValue {
+        key: tag,
+        value: Value::Raw {
+            type_char: header.type_u8,
+            bytes,
+        },
+        header: Some(header),
+        raw: None,
+        span: Some(span(entry_offset, base_offset + input.position())),
+        padding: exact_bytes(input, data_end, state),
+    });
+    Ok(())
+}
+
+/// With [`ParseOptions::exact`] the bytes of `input` from `start` to the current position
+fn exact_bytes(input: &Cursor<&[u8]>, start: u64, state: &ParseState) -> Option<Vec<u8>> {
+    state
+        .options
+        .exact
+        .then(|| input.get_ref()[start as usize..input.position() as usize].to_vec())
+}
+
+/// The span of an entry from its offset to the offset after its padding
+fn span(offset: u64, end: u64) -> (u64, usize) {
+    (offset, (end - offset) as usize)
+}
+
+/// Decode a string up to the first NUL, invalid UTF-8 (e.g. Latin-1 bytes) is replaced with U+FFFD
+fn decode_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Skip the padding that aligns an entry of `num_bytes` data bytes to 32 bits,
+/// padding cut short by the end of the input is reported as a [`GpmfError::MissingPadding`] warning
+fn skip_padding(
+    input: &mut Cursor<&[u8]>,
+    base_offset: u64,
+    num_bytes: usize,
+    tag: &Tag,
+    state: &mut ParseState,
+    entry_offset: u64,
+) -> Result<(), GpmfError> {
+    let mod4 = num_bytes % 4;
+    let padding_bytes = if mod4 == 0 { 0 } else { 4 - mod4 };
+    trace!(
+        "Num Bytes {} Mod4 {} Padding Bytes {}",
+        num_bytes,
+        mod4,
+        padding_bytes
+    );
 
-        if padding_bytes > 0 {
-            debug!("Skipping {} bytes", padding_bytes);
+    if padding_bytes > 0 {
+        debug!("Skipping {} bytes", padding_bytes);
+        let skipped = read_at(input, base_offset, |input| {
             io::copy(
                 &mut input.by_ref().take(padding_bytes as u64),
                 &mut io::sink(),
-            )?;
+            )
+        })?;
+        // extraction tools often trim the padding of the final entry, which is harmless
+        if skipped < padding_bytes as u64 {
+            let missing = padding_bytes - skipped as usize;
+            warn!("Missing {} padding bytes at end of buffer", missing);
+            let end = base_offset + input.position();
+            let error = GpmfError::MissingPadding {
+                tag: tag.clone(),
+                missing,
+                offset: entry_offset,
+            };
+            state.warnings.push(GpmfWarning {
+                error: state.in_entry(error, Some(tag), entry_offset),
+                skipped: end..end,
+            });
         }
     }
-    Ok(res)
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tests::setup;
+    use crate::tests::{capture_warnings, setup};
     use std::path::Path;
 
     fn read_file(path: &str) -> anyhow::Result<Vec<KeyValue>> {
@@ -279,4 +1645,1490 @@ mod tests {
         let res = read_file("karma.raw").unwrap();
         println!("{:?}", res);
     }
+
+    /// Check the elements per sample of every entry in `entries` against its header and value
+    fn check_elements_per_sample(entries: &[KeyValue]) {
+        for kv in entries {
+            match kv.value() {
+                Value::Nested(children) => check_elements_per_sample(children),
+                Value::Simple(samples) => {
+                    let header = kv.header().unwrap();
+                    let typ = Type::try_from(header.type_u8()).unwrap();
+                    let elements = kv.elements_per_sample().unwrap();
+                    assert_eq!(
+                        elements * typ.size(),
+                        header.size() as usize,
+                        "{}",
+                        kv.key()
+                    );
+                    if typ != Type::Char {
+                        assert!(samples.iter().all(|s| s.len() == elements), "{}", kv.key());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_elements_per_sample() {
+        for path in [
+            "hero5.raw",
+            "hero6.raw",
+            "hero6+ble.raw",
+            "Fusion.raw",
+            "karma.raw",
+        ] {
+            check_elements_per_sample(&read_file(path).unwrap());
+        }
+
+        let data = nested(
+            b"STRM",
+            &[
+                klv(b"ACCL", b's', 6, 2, &[0; 12]),
+                klv(b"GPS5", b'l', 20, 1, &[0; 20]),
+                klv(b"SHUT", b'f', 4, 3, &[0; 12]),
+                klv(b"TYPE", b'c', 1, 2, b"lf"),
+                klv(b"FACE", b'?', 8, 1, &[0; 8]),
+            ],
+        );
+        let res = parse_gpmf(&data).unwrap();
+        let Value::Nested(strm) = res[0].value() else {
+            panic!("Expected nested value, got {:?}", res[0].value());
+        };
+        let elements: Vec<_> = strm.iter().map(KeyValue::elements_per_sample).collect();
+        assert_eq!(elements, [Some(3), Some(5), Some(1), Some(1), None]);
+        assert_eq!(res[0].elements_per_sample(), None);
+    }
+
+    /// Check that `reparsed` is `kv` read from its span, the offsets of nested entries moved by `shift`
+    fn assert_same_entry(kv: &KeyValue, reparsed: &KeyValue, shift: u64) {
+        assert_eq!(kv.key(), reparsed.key());
+        assert_eq!(kv.header(), reparsed.header());
+        assert_eq!(kv.offset(), reparsed.offset().map(|offset| offset + shift));
+        assert_eq!(kv.raw_len(), reparsed.raw_len());
+        match (kv.value(), reparsed.value()) {
+            (Value::Nested(children), Value::Nested(reparsed)) => {
+                assert_eq!(children.len(), reparsed.len());
+                for (child, reparsed) in children.iter().zip(reparsed) {
+                    assert_same_entry(child, reparsed, shift);
+                }
+            }
+            (value, reparsed) => assert_eq!(format!("{:?}", value), format!("{:?}", reparsed)),
+        }
+    }
+
+    /// Re-parse every entry in `entries` from its span of `text`
+    fn check_spans(text: &[u8], entries: &[KeyValue], complex: &mut usize) {
+        for kv in entries {
+            let offset = kv.offset().unwrap();
+            let span = &text[offset as usize..offset as usize + kv.raw_len().unwrap()];
+            match kv.value() {
+                // the TYPE is outside of the span
+                Value::Complex(_) => *complex += 1,
+                _ => {
+                    let reparsed = parse_gpmf(span).unwrap();
+                    assert_eq!(reparsed.len(), 1, "{} at offset {}", kv.key(), offset);
+                    assert_same_entry(kv, &reparsed[0], offset);
+                }
+            }
+            if let Value::Nested(children) = kv.value() {
+                check_spans(text, children, complex);
+            }
+        }
+    }
+
+    #[test]
+    fn test_entry_spans() {
+        let text = std::fs::read(Path::new("samples").join("hero6.raw")).unwrap();
+        let res = parse_gpmf(&text).unwrap();
+        assert_eq!(res[0].offset(), Some(0));
+        assert_eq!(
+            res.iter().filter_map(KeyValue::raw_len).sum::<usize>(),
+            text.len()
+        );
+        let mut complex = 0;
+        check_spans(&text, &res, &mut complex);
+
+        // complex entries parse in the span of their stream
+        let text = std::fs::read(Path::new("samples").join("karma.raw")).unwrap();
+        let res = parse_gpmf(&text).unwrap();
+        let mut karma_complex = 0;
+        check_spans(&text, &res, &mut karma_complex);
+        assert!(karma_complex > 0);
+    }
+
+    #[test]
+    fn test_header() {
+        let res = read_file("hero5.raw").unwrap();
+        let header = res[0].header().unwrap();
+        assert_eq!(res[0].key(), &Tag::DEVC);
+        assert_eq!(header.type_u8(), 0);
+        assert_eq!(header.size(), 1);
+        assert_eq!(header.repeat(), 4784);
+
+        // a string padded with NULs inside its declared size
+        let data = b"STNMc\x08\x00\x01abc\x00\x00\x00\x00\x00";
+        let res = parse_gpmf(data).unwrap();
+        let header = res[0].header().unwrap();
+        assert_eq!(
+            (header.type_u8(), header.size(), header.repeat()),
+            (b'c', 8, 1)
+        );
+        assert_eq!(header.data_len(), 8);
+    }
+
+    #[test]
+    fn test_missing_final_padding() {
+        setup();
+        let mut text = std::fs::read(Path::new("samples").join("hero5.raw")).unwrap();
+        let original = parse_gpmf(&text).unwrap();
+
+        // a 5 byte string needs 3 padding bytes which are left out
+        text.extend_from_slice(b"RMRKc\x01\x00\x05hello");
+        let (res, warnings) = parse_gpmf_with_warnings(&text, &ParseOptions::default()).unwrap();
+        assert_eq!(res.len(), original.len() + 1);
+        assert_eq!(res.last().unwrap().key(), &Tag::RMRK);
+        assert_eq!(warnings.len(), 1);
+        let offset = text.len() as u64 - 13;
+        assert!(matches!(
+            warnings[0].error.inner(),
+            GpmfError::MissingPadding { tag: Tag::RMRK, missing: 3, offset: o } if *o == offset
+        ));
+        let end = text.len() as u64;
+        assert_eq!(warnings[0].skipped, end..end);
+    }
+
+    #[test]
+    fn test_missing_padding_of_sample() {
+        let text = std::fs::read(Path::new("samples").join("hero5.raw")).unwrap();
+        // the accelerometer STRM, ending with an ACCL entry followed by 2 padding bytes
+        let strm = &text[48..1380];
+        assert_eq!(&strm[..8], b"STRM\x00\x01\x05\x2c");
+        let mut stripped = strm[..strm.len() - 2].to_vec();
+        stripped[6..8].copy_from_slice(&1322u16.to_be_bytes());
+
+        let original = parse_gpmf(strm).unwrap();
+        let (res, warnings) = capture_warnings(|| parse_gpmf(&stripped));
+        let res = res.unwrap();
+        assert!(warnings.contains("Missing 2 padding bytes"), "{}", warnings);
+        let (_, warnings) = parse_gpmf_with_warnings(&stripped, &ParseOptions::default()).unwrap();
+        // the ACCL and the STRM holding it both lack their padding
+        assert_eq!(warnings.len(), 2);
+        let message = warnings[0].to_string();
+        assert!(message.contains("STRM[0]/ACCL"), "{}", message);
+        assert!(message.contains("missing 2 padding bytes"), "{}", message);
+        assert!(matches!(
+            warnings[1].error.inner(),
+            GpmfError::MissingPadding {
+                tag: Tag::STRM,
+                missing: 2,
+                offset: 0
+            }
+        ));
+        let (Value::Nested(original), Value::Nested(res)) = (original[0].value(), res[0].value())
+        else {
+            panic!("Expected nested values");
+        };
+        assert_eq!(res.last().unwrap().key(), &Tag::ACCL);
+        let values = |entries: &[KeyValue]| {
+            entries
+                .iter()
+                .map(|kv| format!("{:?} {:?}", kv.key(), kv.value()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values(res), values(original));
+        // the span only covers the bytes that are there
+        assert_eq!(
+            res.last().unwrap().raw_len().unwrap() + 2,
+            original.last().unwrap().raw_len().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_missing_nested_padding() {
+        setup();
+        // DEVC holding a single unpadded 5 byte string, the DEVC itself is unpadded too
+        let data = b"DEVC\x00\x01\x00\x0dSTNMc\x01\x00\x05hello";
+        let (res, warnings) = parse_gpmf_with_warnings(data, &ParseOptions::default()).unwrap();
+        let missing: Vec<_> = warnings
+            .iter()
+            .map(|warning| match warning.error.inner() {
+                GpmfError::MissingPadding { tag, missing, .. } => (tag.clone(), *missing),
+                error => panic!("Expected missing padding, got {}", error),
+            })
+            .collect();
+        assert_eq!(missing, [(Tag::STNM, 3), (Tag::DEVC, 3)]);
+        assert_eq!(res.len(), 1);
+        match res[0].value() {
+            Value::Nested(nested) => {
+                assert_eq!(nested.len(), 1);
+                assert_eq!(nested[0].key(), &Tag::STNM);
+                assert!(matches!(nested[0].value(), Value::String(s) if s == "hello"));
+            }
+            v => panic!("Expected nested value, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_date_and_fixed() {
+        let data = b"GPSUU\x10\x00\x01230323191804.123TMPCq\x04\x00\x01\x00\x01\x80\x00";
+        let res = parse_gpmf(data).unwrap();
+        assert_eq!(res.len(), 2);
+
+        let Value::Simple(date) = res[0].value() else {
+            panic!("Expected simple value, got {:?}", res[0].value());
+        };
+        match &date[0][0] {
+            #[cfg(feature = "dates")]
+            Value::Date(date) => {
+                assert_eq!(date.format(DATE_FORMAT).to_string(), "230323191804.123")
+            }
+            #[cfg(not(feature = "dates"))]
+            Value::Date(date) => assert_eq!(date, b"230323191804.123"),
+            v => panic!("Expected date, got {:?}", v),
+        }
+
+        let Value::Simple(fixed) = res[1].value() else {
+            panic!("Expected simple value, got {:?}", res[1].value());
+        };
+        match &fixed[0][0] {
+            #[cfg(feature = "fixedpoint")]
+            Value::Fixed32(fixed) => assert_eq!(*fixed, I16F16::from_num(1.5)),
+            #[cfg(not(feature = "fixedpoint"))]
+            Value::Fixed32(fixed) => assert_eq!(*fixed, 0x0001_8000),
+            v => panic!("Expected fixed point number, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_catching_ok() {
+        let text = std::fs::read(Path::new("samples").join("karma.raw")).unwrap();
+        assert_eq!(
+            parse_gpmf_catching(&text).unwrap().len(),
+            parse_gpmf(&text).unwrap().len()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "dates")]
+    fn test_invalid_dates() {
+        let dates: [&[u8; 16]; 2] = [b"\xff\xfe23\x0019180?.123\x01", b"231332191804.123"];
+        for date in dates {
+            let mut data = b"GPSUU\x10\x00\x01".to_vec();
+            data.extend_from_slice(date);
+            // a following entry must still be parsed
+            data.extend_from_slice(b"GPSFL\x04\x00\x01\x00\x00\x00\x03");
+            let res = parse_gpmf(&data).unwrap();
+            assert_eq!(res.len(), 2);
+            match res[0].value() {
+                Value::Simple(samples) => {
+                    assert!(matches!(&samples[0][0], Value::RawDate(raw) if raw == date))
+                }
+                v => panic!("Expected simple value, got {:?}", v),
+            }
+            assert_eq!(res[1].key(), &Tag::GPSF);
+        }
+    }
+
+    /// Parse a GPSU entry holding `date` and return its value
+    fn read_gpsu(date: &[u8; 16]) -> Value {
+        let res = parse_gpmf(&klv(b"GPSU", b'U', 16, 1, date)).unwrap();
+        let Value::Simple(samples) = res[0].value() else {
+            panic!("Expected simple value, got {:?}", res[0].value());
+        };
+        samples[0][0].clone()
+    }
+
+    #[test]
+    fn test_gpsu_as_string() {
+        let text = std::fs::read(Path::new("samples").join("hero5.raw")).unwrap();
+        let offset = 3928;
+        assert_eq!(&text[offset..offset + 8], b"GPSUU\x10\x00\x01");
+        let mut as_string = text.clone();
+        as_string[offset + 4..offset + 8].copy_from_slice(b"c\x01\x00\x10");
+
+        /// Find the value of the first GPSU entry
+        fn gpsu(entries: &[KeyValue]) -> Option<String> {
+            entries.iter().find_map(|kv| match kv.value() {
+                Value::Nested(children) => gpsu(children),
+                v if kv.key() == &Tag::GPSU => Some(format!("{:?}", v)),
+                _ => None,
+            })
+        }
+        let date = gpsu(&parse_gpmf(&text).unwrap()).unwrap();
+        assert_eq!(gpsu(&parse_gpmf(&as_string).unwrap()).unwrap(), date);
+        #[cfg(feature = "dates")]
+        assert!(date.contains("2017-04-17T17:31:03Z"), "{}", date);
+
+        // not a date, kept as the string
+        let res = parse_gpmf(&klv(b"GPSU", b'c', 1, 16, b"not a valid date")).unwrap();
+        assert!(matches!(res[0].value(), Value::String(s) if s == "not a valid date"));
+    }
+
+    #[test]
+    fn test_no_date() {
+        let dates: [&[u8; 16]; 4] = [
+            &[0; 16],
+            b"                ",
+            b"000000000000.000",
+            b"000000000000\0\0\0\0",
+        ];
+        for date in dates {
+            assert!(matches!(read_gpsu(date), Value::NoDate(raw) if raw == *date));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dates")]
+    fn test_short_dates() {
+        for date in [b"230323191804.12\0", b"230323191804.12 "] {
+            let Value::Date(date) = read_gpsu(date) else {
+                panic!("Expected a date for {:?}", date);
+            };
+            assert_eq!(date.format(DATE_FORMAT).to_string(), "230323191804.120");
+        }
+    }
+
+    #[test]
+    fn test_fourcc_value_bytes() {
+        let fourcc = [0x80, b'A', 0xFF, b'z'];
+        let data = [
+            klv(b"TYPE", b'F', 4, 2, &[*b"ACCL", fourcc].concat()),
+            klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]),
+        ]
+        .concat();
+        let res = parse_gpmf(&data).unwrap();
+        let Value::Simple(samples) = res[0].value() else {
+            panic!("Expected simple value, got {:?}", res[0].value());
+        };
+        let [Value::Tag(known)] = &samples[0][..] else {
+            panic!("Expected a tag, got {:?}", samples[0]);
+        };
+        let [Value::Tag(other)] = &samples[1][..] else {
+            panic!("Expected a tag, got {:?}", samples[1]);
+        };
+        assert_eq!(known, &Tag::ACCL);
+        assert_eq!(known.fourcc_bytes(), *b"ACCL");
+        assert_eq!(other, &Tag::Other(fourcc));
+        assert_eq!(other.fourcc_bytes(), fourcc);
+        assert_eq!(Tag::from_fourcc(other.fourcc_bytes()), *other);
+    }
+
+    #[test]
+    fn test_bad_type_def() {
+        let data = b"TSMPL\x04\x00\x01\x00\x00\x00\x01TYPEc\x01\x00\x04lZf\x00";
+        let err = parse_gpmf_catching(data).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::UnknownType {
+                type_u8: b'Z',
+                offset: 21
+            }
+        ));
+        let message = err.to_string();
+        assert!(message.contains("'Z'"), "{}", message);
+        assert!(message.contains("offset 21"), "{}", message);
+    }
+
+    #[test]
+    fn test_empty_top_level_container() {
+        let data = b"DEVC\x00\x01\x00\x00DVIDL\x04\x00\x01\x00\x00\x00\x01STRM\x00\x01\x00\x0cTSMPL\x04\x00\x01\x00\x00\x00\x05";
+        let (res, warnings) = parse_gpmf_with_warnings(data, &ParseOptions::default()).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            warnings[0].error.inner(),
+            GpmfError::EmptyContainer {
+                tag: Tag::DEVC,
+                extended: 32,
+                offset: 0
+            }
+        ));
+        assert_eq!(warnings[0].skipped, 8..8);
+        assert!(warnings[0].to_string().contains("Empty DEVC at offset 0"));
+        let (_, lossy) = parse_gpmf_lossy(data);
+        assert_eq!(lossy.len(), 1);
+        assert_eq!(res.len(), 1);
+        let Value::Nested(children) = res[0].value() else {
+            panic!("Expected nested value, got {:?}", res[0].value());
+        };
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].key(), &Tag::DVID);
+        assert_eq!(children[1].key(), &Tag::STRM);
+    }
+
+    #[test]
+    fn test_empty_nested_container() {
+        // at deeper levels an empty container stays empty
+        let data = b"DEVC\x00\x01\x00\x14STRM\x00\x01\x00\x00TSMPL\x04\x00\x01\x00\x00\x00\x05";
+        let res = parse_gpmf(data).unwrap();
+        let Value::Nested(children) = res[0].value() else {
+            panic!("Expected nested value, got {:?}", res[0].value());
+        };
+        assert_eq!(children.len(), 2);
+        assert!(matches!(children[0].value(), Value::Nested(strm) if strm.is_empty()));
+        assert_eq!(children[1].key(), &Tag::TSMP);
+    }
+
+    #[test]
+    fn test_unknown_type() {
+        let data =
+            b"XYZWZ\x03\x00\x02\x01\x02\x03\x04\x05\x06\x00\x00TSMPL\x04\x00\x01\x00\x00\x00\x01";
+        let res = parse_gpmf(data).unwrap();
+        assert_eq!(res.len(), 2);
+        match res[0].value() {
+            Value::Raw { type_char, bytes } => {
+                assert_eq!(*type_char, b'Z');
+                assert_eq!(bytes, &[1, 2, 3, 4, 5, 6]);
+            }
+            v => panic!("Expected raw value, got {:?}", v),
+        }
+        assert_eq!(res[1].key(), &Tag::TSMP);
+    }
+
+    #[test]
+    fn test_type_def() {
+        assert_eq!(
+            parse_type_def(b"lLf\x00\x00", 0).unwrap(),
+            vec![Type::I32, Type::U32, Type::F32]
+        );
+        assert!(parse_type_def(b"lZf", 0).is_err());
+        assert!(parse_type_def(b"", 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_type_def_array_counts() {
+        assert_eq!(parse_type_def(b"f[4]", 0).unwrap(), vec![Type::F32; 4]);
+        assert_eq!(parse_type_def(b"c[4]\x00", 0).unwrap(), vec![Type::Char; 4]);
+        assert_eq!(
+            parse_type_def(b"Lf[3]B", 0).unwrap(),
+            vec![Type::U32, Type::F32, Type::F32, Type::F32, Type::U8]
+        );
+        assert_eq!(parse_type_def(b"l[1]", 0).unwrap(), vec![Type::I32]);
+
+        let err = parse_type_def(b"Lf[3", 10).unwrap_err().to_string();
+        assert!(
+            err.contains("Unterminated") && err.contains("offset 12"),
+            "{}",
+            err
+        );
+        let err = parse_type_def(b"[2]f", 0).unwrap_err().to_string();
+        assert!(err.contains("without a type"), "{}", err);
+        for def in [&b"f[]"[..], b"f[0]", b"f[x]", b"f[-1]"] {
+            let err = parse_type_def(def, 0).unwrap_err().to_string();
+            assert!(err.contains("Invalid array count"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_type_def_count_overflow() {
+        assert_eq!(parse_type_def(b"B[255]", 0).unwrap().len(), 255);
+        assert_eq!(parse_type_def(b"lB[251]", 0).unwrap().len(), 252);
+        for def in [
+            &b"B[256]"[..],
+            b"lB[255]",
+            b"f[4294967296]",
+            b"f[18446744073709551615]",
+        ] {
+            let err = parse_type_def(def, 0).unwrap_err();
+            assert!(matches!(err, GpmfError::InvalidTypeDef { .. }), "{:?}", err);
+            assert!(err.to_string().contains("longer than 255"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_total_values_overflow() {
+        // the total of a long parse on a 32 bit target, simulated with the largest usize
+        let options = ParseOptions {
+            limits: ParseLimits {
+                max_total_values: usize::MAX,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let data = klv(b"ACCL", b's', 6, 1, &[0; 6]);
+        let mut state = ParseState::new(&options, false);
+        state.total_values = usize::MAX - 3;
+        assert!(parse_entries(&data, 0, 0, &mut state).is_ok());
+        assert_eq!(state.total_values, usize::MAX);
+
+        let mut state = ParseState::new(&options, false);
+        state.total_values = usize::MAX - 2;
+        let err = parse_entries(&data, 0, 0, &mut state).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::LimitExceeded(LimitExceeded {
+                limit: Limit::TotalValues,
+                offset: 0,
+                ..
+            })
+        ));
+        assert_eq!(state.total_values, usize::MAX - 2);
+    }
+
+    #[test]
+    fn test_complex_with_array_counts() {
+        let mut complex = 9u32.to_be_bytes().to_vec();
+        for f in [0.5f32, 1.5, 2.5] {
+            complex.extend_from_slice(&f.to_be_bytes());
+        }
+        complex.push(3);
+        let data = nested(
+            b"STRM",
+            &[
+                klv(b"TYPE", b'c', 1, 6, b"Lf[3]B"),
+                klv(b"FACE", b'?', 17, 1, &complex),
+            ],
+        );
+        let res = parse_gpmf(&data).unwrap();
+        let Value::Nested(children) = res[0].value() else {
+            panic!("Expected nested value, got {:?}", res[0].value());
+        };
+        let Value::Complex(samples) = children[1].value() else {
+            panic!("Expected complex value, got {:?}", children[1].value());
+        };
+        assert!(matches!(
+            samples[0][..],
+            [Value::U32(9), Value::F32(a), Value::F32(b), Value::F32(c), Value::U8(3)]
+                if a == 0.5 && b == 1.5 && c == 2.5
+        ));
+    }
+
+    #[test]
+    fn test_complex_with_strings() {
+        let mut complex = Vec::new();
+        for (id, name, score) in [(7u32, b"face", 0.25f32), (8, b"ab\0\0", 0.75)] {
+            complex.extend_from_slice(&id.to_be_bytes());
+            complex.extend_from_slice(name);
+            complex.extend_from_slice(&score.to_be_bytes());
+        }
+        complex.extend_from_slice(b"x");
+        complex.extend_from_slice(b"SHUT");
+        let data = nested(
+            b"STRM",
+            &[
+                klv(b"TYPE", b'c', 1, 6, b"Lc[4]f"),
+                klv(b"FACE", b'?', 12, 2, &complex[..24]),
+                klv(b"TYPE", b'c', 1, 2, b"cF"),
+                klv(b"FACE", b'?', 5, 1, &complex[24..]),
+            ],
+        );
+        let res = parse_gpmf(&data).unwrap();
+        let Value::Nested(children) = res[0].value() else {
+            panic!("Expected nested value, got {:?}", res[0].value());
+        };
+        let Value::Complex(samples) = children[1].value() else {
+            panic!("Expected complex value, got {:?}", children[1].value());
+        };
+        assert_eq!(samples.len(), 2);
+        assert!(matches!(
+            &samples[0][..],
+            [Value::U32(7), Value::String(name), Value::F32(score)] if name == "face" && *score == 0.25
+        ));
+        assert!(matches!(
+            &samples[1][..],
+            [Value::U32(8), Value::String(name), Value::F32(score)] if name == "ab" && *score == 0.75
+        ));
+        let Value::Complex(samples) = children[3].value() else {
+            panic!("Expected complex value, got {:?}", children[3].value());
+        };
+        assert!(matches!(
+            &samples[0][..],
+            [Value::String(c), Value::Tag(Tag::SHUT)] if c == "x"
+        ));
+    }
+
+    /// Encode a single KLV entry including its padding
+    fn klv(tag: &[u8; 4], type_u8: u8, size: u8, repeat: u16, data: &[u8]) -> Vec<u8> {
+        let mut res = tag.to_vec();
+        res.push(type_u8);
+        res.push(size);
+        res.extend_from_slice(&repeat.to_be_bytes());
+        res.extend_from_slice(data);
+        res.resize(res.len() + (4 - data.len() % 4) % 4, 0);
+        res
+    }
+
+    /// Encode a nested container holding the already encoded children
+    fn nested(tag: &[u8; 4], children: &[Vec<u8>]) -> Vec<u8> {
+        let data = children.concat();
+        klv(tag, 0, 1, data.len() as u16, &data)
+    }
+
+    #[test]
+    fn test_unexpected_eof() {
+        // the second sample is missing
+        let data = b"DEVC\x00\x01\x00\x10TSMPL\x04\x00\x02\x00\x00\x00\x01\x00\x00\x00\x02";
+        assert!(parse_gpmf(data).is_ok());
+        let err = parse_gpmf(&data[..16]).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::NestedOverrun { offset: 0, .. }
+        ));
+        let err = parse_gpmf(&data[8..16]).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::EntryOverrun {
+                tag: Tag::TSMP,
+                declared: 8,
+                remaining: 0,
+                offset: 0
+            }
+        ));
+        assert_eq!(err.offset(), Some(0));
+        let err = parse_gpmf(&data[8..18]).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::EntryOverrun { remaining: 2, .. }
+        ));
+        let err = parse_gpmf(&data[8..22]).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::EntryOverrun { remaining: 6, .. }
+        ));
+        // a header cut short
+        let err = parse_gpmf(&data[8..14]).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::UnexpectedEof { offset: 6 }
+        ));
+    }
+
+    /// Assert that `partial` is `full` cut short, nested containers included
+    fn assert_prefix(partial: &[KeyValue], full: &[KeyValue]) {
+        assert!(partial.len() <= full.len());
+        for (p, f) in partial.iter().zip(full) {
+            assert_eq!(p.key(), f.key());
+            match (p.value(), f.value()) {
+                (Value::Nested(p), Value::Nested(f)) => assert_prefix(p, f),
+                (p, f) => assert_eq!(format!("{:?}", p), format!("{:?}", f)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_lossy_truncated() {
+        let text = std::fs::read(Path::new("samples").join("karma.raw")).unwrap();
+        let full = parse_gpmf(&text).unwrap();
+
+        let (res, warnings) = parse_gpmf_lossy(&text);
+        assert!(warnings.is_empty());
+        assert_prefix(&res, &full);
+        assert_eq!(res.len(), full.len());
+
+        for len in (0..text.len()).step_by(97) {
+            let (res, warnings) = parse_gpmf_lossy(&text[..len]);
+            assert_prefix(&res, &full);
+            if len > 8 {
+                assert!(!warnings.is_empty(), "truncated to {} bytes", len);
+                assert!(!res.is_empty(), "truncated to {} bytes", len);
+            }
+        }
+
+        // the first DEVC is complete in the first 95%
+        let (res, warnings) = parse_gpmf_lossy(&text[..text.len() * 95 / 100]);
+        assert_eq!(res.len(), 2);
+        assert_eq!(format!("{:?}", res[0]), format!("{:?}", full[0]));
+        assert!(matches!(
+            warnings[0].error.inner(),
+            GpmfError::NestedOverrun { offset: 4216, .. }
+        ));
+    }
+
+    #[test]
+    fn test_lossy_keeps_siblings() {
+        let corrupt = nested(
+            b"STRM",
+            &[
+                klv(b"STNM", b'c', 1, 3, b"bad"),
+                klv(b"TYPE", b'c', 1, 2, b"lZ"),
+                klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]),
+            ],
+        );
+        let fine = nested(b"STRM", &[klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 2])]);
+        let data = nested(b"DEVC", &[corrupt, fine]);
+        assert!(parse_gpmf(&data).is_err());
+
+        let (res, warnings) = parse_gpmf_lossy(&data);
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            warnings[0].error.inner(),
+            GpmfError::UnknownType { type_u8: b'Z', .. }
+        ));
+        // from the bad type letter to the end of the corrupt STRM
+        assert_eq!(warnings[0].skipped, 37..52);
+
+        let Value::Nested(streams) = res[0].value() else {
+            panic!("Expected nested value, got {:?}", res[0].value());
+        };
+        assert_eq!(streams.len(), 2);
+        let Value::Nested(corrupt) = streams[0].value() else {
+            panic!("Expected nested value, got {:?}", streams[0].value());
+        };
+        assert_eq!(corrupt.len(), 1);
+        assert_eq!(corrupt[0].key(), &Tag::STNM);
+        let Value::Nested(fine) = streams[1].value() else {
+            panic!("Expected nested value, got {:?}", streams[1].value());
+        };
+        assert_eq!(fine[0].key(), &Tag::TSMP);
+    }
+
+    #[test]
+    fn test_fixed_width_strings() {
+        // the GPS units of a HERO5, padded with NULs to 3 characters
+        let res = read_file("hero5.raw").unwrap();
+        let unit = find_entries(&res, &Tag::UNIT)
+            .find(|kv| matches!(kv.value(), Value::Strings(units) if units.len() == 5))
+            .unwrap();
+        assert!(
+            matches!(unit.value(), Value::Strings(units) if units == &["deg", "deg", "m", "m/s", "m/s"])
+        );
+        let fields: Vec<_> = unit.raw_strings().unwrap().collect();
+        assert_eq!(fields, [&b"deg"[..], b"deg", b"m\0\0", b"m/s", b"m/s"]);
+
+        // the units of a Karma, some of them empty
+        let res = read_file("karma.raw").unwrap();
+        let unit = find_entries(&res, &Tag::UNIT)
+            .find(|kv| matches!(kv.value(), Value::Strings(units) if units.len() == 15))
+            .unwrap();
+        let Value::Strings(units) = unit.value() else {
+            unreachable!()
+        };
+        assert_eq!(units[..4], ["A", "Ah", "J", "degC"]);
+        assert_eq!(units[10], "");
+        assert_eq!(units[14], "%");
+        let fields: Vec<_> = unit.raw_strings().unwrap().collect();
+        assert_eq!(fields.len(), 15);
+        assert!(fields.iter().all(|field| field.len() == 4));
+        assert_eq!(fields[1], b"Ah\0\0");
+
+        // bytes after a NUL and a leading NUL
+        let data = klv(b"UNIT", b'c', 3, 3, b"m\0xdeg\0ab");
+        let res = parse_gpmf(&data).unwrap();
+        assert!(matches!(res[0].value(), Value::Strings(units) if units == &["m", "deg", ""]));
+        let fields: Vec<_> = res[0].raw_strings().unwrap().collect();
+        assert_eq!(fields, [&b"m\0x"[..], b"deg", b"\0ab"]);
+
+        let data = klv(b"DVNM", b'c', 1, 6, b"HERO\0\0");
+        let res = parse_gpmf(&data).unwrap();
+        let fields: Vec<_> = res[0].raw_strings().unwrap().collect();
+        assert_eq!(fields, [b"HERO\0\0"]);
+    }
+
+    /// All entries with `tag` in `entries` and their nested containers
+    fn find_entries<'a>(
+        entries: &'a [KeyValue],
+        tag: &'a Tag,
+    ) -> Box<dyn Iterator<Item = &'a KeyValue> + 'a> {
+        Box::new(entries.iter().flat_map(move |kv| {
+            let nested: Box<dyn Iterator<Item = &KeyValue>> = match kv.value() {
+                Value::Nested(children) => find_entries(children, tag),
+                _ => Box::new(std::iter::empty()),
+            };
+            (kv.key() == tag).then_some(kv).into_iter().chain(nested)
+        }))
+    }
+
+    #[test]
+    fn test_non_ascii_strings() {
+        let data = [
+            klv(b"DVNM", b'c', 1, 13, "Sensor 20\u{b0}C\0".as_bytes()),
+            klv(b"SIUN", b'c', 3, 2, b"\xb0C\0m/s"),
+        ]
+        .concat();
+        let res = parse_gpmf(&data).unwrap();
+        assert!(matches!(res[0].value(), Value::String(s) if s == "Sensor 20\u{b0}C"));
+        let Value::Strings(units) = res[1].value() else {
+            panic!("Expected strings, got {:?}", res[1].value());
+        };
+        assert_eq!(units, &["\u{fffd}C", "m/s"]);
+    }
+
+    #[test]
+    fn test_invalid_fourcc() {
+        let mut text = std::fs::read(Path::new("samples").join("hero5.raw")).unwrap();
+        // flip a bit of the D in DVNM
+        assert_eq!(&text[20..24], b"DVNM");
+        text[20] ^= 0x40;
+        let err = parse_gpmf(&text).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::InvalidFourCC {
+                fourcc: [0x04, b'V', b'N', b'M'],
+                offset: 20
+            }
+        ));
+        assert!(
+            err.to_string().contains("\"\\x04VNM\" at offset 20"),
+            "{}",
+            err
+        );
+
+        // printable but unsupported keys are still read
+        let res = parse_gpmf(&klv(b"ab c", b'L', 4, 1, &[0, 0, 0, 1])).unwrap();
+        assert_eq!(res[0].key(), &Tag::Other(*b"ab c"));
+    }
+
+    #[test]
+    fn test_resync() {
+        let text = std::fs::read(Path::new("samples").join("Fusion.raw")).unwrap();
+        let full = parse_gpmf(&text).unwrap();
+        let Value::Nested(full) = full[0].value() else {
+            panic!("Expected nested value, got {:?}", full[0].value());
+        };
+
+        // wipe the header and first entries of the second of six STRMs
+        let mut corrupt = text.clone();
+        assert_eq!(&corrupt[2564..2568], b"STRM");
+        corrupt[2564..2596].fill(0xff);
+        assert!(parse_gpmf(&corrupt).is_err());
+
+        let options = ParseOptions {
+            resync: true,
+            ..Default::default()
+        };
+        let (res, warnings) = parse_gpmf_with_warnings(&corrupt, &options).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            warnings[0].error.inner(),
+            GpmfError::InvalidFourCC { offset: 2564, .. }
+        ));
+        // up to the third STRM
+        assert_eq!(warnings[0].skipped, 2564..22488);
+
+        let Value::Nested(devc) = res[0].value() else {
+            panic!("Expected nested value, got {:?}", res[0].value());
+        };
+        assert_eq!(devc.len(), full.len() - 1);
+        let expected: Vec<_> = full[..4].iter().chain(&full[5..]).collect();
+        for (kv, expected) in devc.iter().zip(expected) {
+            assert_eq!(format!("{:?}", kv), format!("{:?}", expected));
+        }
+    }
+
+    #[test]
+    fn test_resync_without_candidate() {
+        let data = [
+            klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]),
+            vec![0xff; 8],
+            klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 2]),
+        ]
+        .concat();
+        let options = ParseOptions {
+            resync: true,
+            ..Default::default()
+        };
+        let err = parse_gpmf_with_warnings(&data, &options).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::InvalidFourCC { offset: 12, .. }
+        ));
+    }
+
+    #[test]
+    fn test_complex_without_type_def() {
+        let data = b"FACE?\x04\x00\x01\x00\x00\x00\x00";
+        let err = parse_gpmf_catching(data).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::MissingTypeDef {
+                tag: Tag::FACE,
+                offset: 0
+            }
+        ));
+        let message = err.to_string();
+        assert!(message.contains("FACE at offset 0"), "{}", message);
+    }
+
+    #[test]
+    fn test_size_not_multiple_of_type() {
+        let data = [
+            klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]),
+            klv(b"ACCL", b's', 7, 1, &[0; 7]),
+        ]
+        .concat();
+        let err = parse_gpmf(&data).unwrap_err().to_string();
+        assert!(err.contains("ACCL at offset 12"), "{}", err);
+        assert!(err.contains("size of 7 bytes"), "{}", err);
+        assert!(err.contains("2 byte size of type I16"), "{}", err);
+
+        let mut complex = 1i32.to_be_bytes().to_vec();
+        complex.extend_from_slice(&[0; 5]);
+        let data = nested(
+            b"STRM",
+            &[
+                klv(b"TYPE", b'c', 1, 2, b"lf"),
+                klv(b"FACE", b'?', 9, 1, &complex),
+            ],
+        );
+        let err = parse_gpmf(&data).unwrap_err().to_string();
+        assert!(err.contains("FACE at offset 20"), "{}", err);
+        assert!(err.contains("8 byte size of type Complex"), "{}", err);
+    }
+
+    #[test]
+    fn test_strict_unknown_tag() {
+        let data = [
+            klv(b"XYZW", b'B', 1, 3, &[1, 2, 3]),
+            klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]),
+        ]
+        .concat();
+        let err = parse_gpmf_opts(&data, &ParseOptions::strict()).unwrap_err();
+        assert!(
+            matches!(err.inner(), GpmfError::UnknownTag { tag: Tag::Other(fourcc), offset: 0 } if fourcc == b"XYZW"),
+            "{:?}",
+            err
+        );
+
+        let res = parse_gpmf_opts(&data, &ParseOptions::lenient()).unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].key(), &Tag::Other(*b"XYZW"));
+
+        let options = ParseOptions {
+            unknown_tags: UnknownTags::Skip,
+            ..Default::default()
+        };
+        let res = parse_gpmf_opts(&data, &options).unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].key(), &Tag::TSMP);
+    }
+
+    #[test]
+    fn test_strict_unknown_type() {
+        let data = [
+            klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]),
+            klv(b"ACCL", b'Z', 2, 1, &[1, 2]),
+        ]
+        .concat();
+        let err = parse_gpmf_opts(&data, &ParseOptions::strict()).unwrap_err();
+        assert!(
+            matches!(
+                err.inner(),
+                GpmfError::UnknownType {
+                    type_u8: b'Z',
+                    offset: 16
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        let res = parse_gpmf_opts(&data, &ParseOptions::lenient()).unwrap();
+        assert_eq!(res.len(), 2);
+        assert!(
+            matches!(res[1].value(), Value::Raw { type_char: b'Z', bytes } if bytes == &[1, 2])
+        );
+    }
+
+    #[test]
+    fn test_lenient_misaligned_size() {
+        let data = [
+            klv(b"ACCL", b's', 7, 1, &[1, 2, 3, 4, 5, 6, 7]),
+            klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]),
+        ]
+        .concat();
+        let err = parse_gpmf_opts(&data, &ParseOptions::strict()).unwrap_err();
+        assert!(
+            matches!(
+                err.inner(),
+                GpmfError::InvalidSize {
+                    size: 7,
+                    offset: 0,
+                    ..
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        let (res, warnings) = parse_gpmf_with_warnings(&data, &ParseOptions::lenient()).unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].key(), &Tag::ACCL);
+        assert!(
+            matches!(res[0].value(), Value::Raw { type_char: b's', bytes } if bytes == &[1, 2, 3, 4, 5, 6, 7])
+        );
+        assert_eq!(res[1].key(), &Tag::TSMP);
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            warnings[0].error.inner(),
+            GpmfError::InvalidSize { .. }
+        ));
+        assert_eq!(warnings[0].skipped, 8..15);
+    }
+
+    #[test]
+    fn test_lenient_missing_type_def() {
+        let data = nested(
+            b"STRM",
+            &[
+                klv(b"FACE", b'?', 4, 2, &[1, 2, 3, 4, 5, 6, 7, 8]),
+                klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]),
+            ],
+        );
+        let err = parse_gpmf_opts(&data, &ParseOptions::strict()).unwrap_err();
+        assert!(
+            matches!(
+                err.inner(),
+                GpmfError::MissingTypeDef {
+                    tag: Tag::FACE,
+                    offset: 8
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        let (res, warnings) = parse_gpmf_with_warnings(&data, &ParseOptions::lenient()).unwrap();
+        let Value::Nested(strm) = res[0].value() else {
+            panic!("Expected nested value, got {:?}", res[0].value());
+        };
+        assert_eq!(strm.len(), 2);
+        assert!(
+            matches!(strm[0].value(), Value::Raw { type_char: b'?', bytes } if bytes == &[1, 2, 3, 4, 5, 6, 7, 8])
+        );
+        assert_eq!(strm[1].key(), &Tag::TSMP);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].skipped, 16..24);
+    }
+
+    #[test]
+    fn test_lenient_complex_without_type_def() {
+        // like a re-encoded file with the TYPE stripped, samples of 5 bytes needing padding
+        let samples: Vec<u8> = (1..=15).collect();
+        let data = [
+            klv(b"FACE", b'?', 5, 3, &samples),
+            klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]),
+        ]
+        .concat();
+        assert!(parse_gpmf(&data).is_err());
+
+        let (res, warnings) = parse_gpmf_with_warnings(&data, &ParseOptions::lenient()).unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].key(), &Tag::FACE);
+        assert!(
+            matches!(res[0].value(), Value::Raw { type_char: b'?', bytes } if bytes == &samples)
+        );
+        assert_eq!(res[0].header().map(Header::data_len), Some(15));
+        assert_eq!(res[1].key(), &Tag::TSMP);
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            warnings[0].error.inner(),
+            GpmfError::MissingTypeDef {
+                tag: Tag::FACE,
+                offset: 0
+            }
+        ));
+        assert!(warnings[0].to_string().starts_with("FACE @ offset 0x0: "));
+        assert_eq!(warnings[0].skipped, 8..23);
+    }
+
+    #[test]
+    fn test_zero_repeat() {
+        let data = nested(
+            b"STRM",
+            &[
+                klv(b"TSMP", b'L', 4, 0, &[]),
+                klv(b"STNM", b'c', 1, 0, &[]),
+                klv(b"SIUN", b'c', 4, 0, &[]),
+                klv(b"FACE", b'?', 8, 0, &[]),
+                klv(b"ACCL", b's', 0, 3, &[]),
+                klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 5]),
+            ],
+        );
+        let res = parse_gpmf(&data).unwrap();
+        let Value::Nested(children) = res[0].value() else {
+            panic!("Expected nested value, got {:?}", res[0].value());
+        };
+        assert_eq!(children.len(), 6);
+        let types = [Type::U32, Type::Char, Type::Char, Type::Complex, Type::I16];
+        for (child, typ) in children.iter().zip(types) {
+            assert!(
+                matches!(child.value(), Value::Empty(t) if *t == typ),
+                "{:?}",
+                child
+            );
+            assert_eq!(child.header().unwrap().data_len(), 0);
+        }
+        // the cursor is still aligned for the entry after them
+        let Value::Simple(samples) = children[5].value() else {
+            panic!("Expected simple value, got {:?}", children[5].value());
+        };
+        assert!(matches!(samples[0][..], [Value::U32(5)]));
+    }
+
+    /// Assert that parsing `data` with `limits` fails on `limit` for the entry at `offset`
+    fn assert_limit(data: &[u8], limits: ParseLimits, limit: Limit, offset: u64) {
+        let err = parse_gpmf_with_limits(data, limits).unwrap_err();
+        let GpmfError::LimitExceeded(exceeded) = err.inner() else {
+            panic!("Expected a limit error, got {:?}", err);
+        };
+        assert_eq!(exceeded.limit, limit);
+        assert_eq!(exceeded.offset, offset);
+    }
+
+    #[test]
+    fn test_max_nesting() {
+        let mut data = klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]);
+        for _ in 0..5000 {
+            data = nested(b"DEVC", &[data]);
+        }
+        let defaults = ParseLimits::default();
+        let offset = 8 * defaults.max_nesting as u64;
+        assert_limit(&data, defaults, Limit::Nesting, offset);
+
+        let mut data = klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]);
+        for _ in 0..3 {
+            data = nested(b"DEVC", &[data]);
+        }
+        let limits = |max_nesting| ParseLimits {
+            max_nesting,
+            ..Default::default()
+        };
+        assert!(parse_gpmf_with_limits(&data, limits(3)).is_ok());
+        assert_limit(&data, limits(2), Limit::Nesting, 16);
+    }
+
+    #[test]
+    fn test_max_total_values() {
+        let data = nested(
+            b"STRM",
+            &[
+                klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]),
+                klv(b"ACCL", b's', 6, 2, &[0; 12]),
+                klv(b"STNM", b'c', 1, 3, b"abc"),
+            ],
+        );
+        let limits = |max_total_values| ParseLimits {
+            max_total_values,
+            ..Default::default()
+        };
+        assert!(parse_gpmf_with_limits(&data, limits(8)).is_ok());
+        assert_limit(&data, limits(7), Limit::TotalValues, 40);
+        assert_limit(&data, limits(6), Limit::TotalValues, 20);
+
+        let mut complex = 1i32.to_be_bytes().to_vec();
+        complex.extend_from_slice(&[0; 4]);
+        let data = [
+            klv(b"TYPE", b'c', 1, 2, b"lf"),
+            klv(b"FACE", b'?', 8, 1, &complex),
+        ]
+        .concat();
+        // the TYPE string counts as one value
+        assert!(parse_gpmf_with_limits(&data, limits(3)).is_ok());
+        assert_limit(&data, limits(2), Limit::TotalValues, 12);
+    }
+
+    #[test]
+    fn test_max_string_len() {
+        let data = [
+            klv(b"STNM", b'c', 1, 5, b"hello"),
+            klv(b"SIUN", b'c', 3, 2, b"m/sdeg"),
+        ]
+        .concat();
+        let limits = |max_string_len| ParseLimits {
+            max_string_len,
+            ..Default::default()
+        };
+        assert!(parse_gpmf_with_limits(&data, limits(5)).is_ok());
+        assert_limit(&data, limits(4), Limit::StringLen, 0);
+        assert!(parse_gpmf_with_limits(&data[16..], limits(3)).is_ok());
+        assert_limit(&data[16..], limits(2), Limit::StringLen, 0);
+    }
+
+    #[test]
+    fn test_max_payload_bytes() {
+        let data = [
+            klv(b"TSMP", b'L', 4, 1, &[0, 0, 0, 1]),
+            klv(b"ACCL", b's', 6, 2, &[0; 12]),
+        ]
+        .concat();
+        let limits = |max_payload_bytes| ParseLimits {
+            max_payload_bytes,
+            ..Default::default()
+        };
+        assert!(parse_gpmf_with_limits(&data, limits(12)).is_ok());
+        assert_limit(&data, limits(11), Limit::PayloadBytes, 12);
+        // checked before anything is read, so the data does not have to be present
+        assert_limit(b"ACCLs\x06\xff\xff", limits(1024), Limit::PayloadBytes, 0);
+    }
+
+    #[test]
+    fn test_type_def_redefined() {
+        let mut first = 7i32.to_be_bytes().to_vec();
+        first.extend_from_slice(&1.5f32.to_be_bytes());
+        let data = nested(
+            b"STRM",
+            &[
+                klv(b"TYPE", b'c', 1, 2, b"lf"),
+                klv(b"FACE", b'?', 8, 1, &first),
+                klv(b"TYPE", b'c', 1, 2, b"BS"),
+                klv(b"FACE", b'?', 3, 2, &[1, 0, 2, 3, 0, 4]),
+            ],
+        );
+        let res = parse_gpmf(&data).unwrap();
+        let Value::Nested(strm) = res[0].value() else {
+            panic!("Expected nested value, got {:?}", res[0].value());
+        };
+        let Value::Complex(face) = strm[1].value() else {
+            panic!("Expected complex value, got {:?}", strm[1].value());
+        };
+        assert!(
+            matches!(face[..], [ref s] if matches!(s[..], [Value::I32(7), Value::F32(f)] if f == 1.5))
+        );
+        let Value::Complex(face) = strm[3].value() else {
+            panic!("Expected complex value, got {:?}", strm[3].value());
+        };
+        assert_eq!(face.len(), 2);
+        assert!(matches!(face[0][..], [Value::U8(1), Value::U16(2)]));
+        assert!(matches!(face[1][..], [Value::U8(3), Value::U16(4)]));
+
+        // a TYPE only applies to the entries after it
+        let data = nested(
+            b"STRM",
+            &[
+                klv(b"FACE", b'?', 8, 1, &first),
+                klv(b"TYPE", b'c', 1, 2, b"lf"),
+            ],
+        );
+        let err = parse_gpmf(&data).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::MissingTypeDef {
+                tag: Tag::FACE,
+                offset: 8
+            }
+        ));
+
+        // nor to the parent container
+        let data = nested(
+            b"DEVC",
+            &[
+                nested(b"STRM", &[klv(b"TYPE", b'c', 1, 2, b"lf")]),
+                klv(b"FACE", b'?', 8, 1, &first),
+            ],
+        );
+        let err = parse_gpmf(&data).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::MissingTypeDef { tag: Tag::FACE, .. }
+        ));
+    }
+
+    #[test]
+    fn test_type_def_scoped_to_stream() {
+        let mut complex = 7i32.to_be_bytes().to_vec();
+        complex.extend_from_slice(&1.5f32.to_be_bytes());
+        let first = nested(
+            b"STRM",
+            &[
+                klv(b"TYPE", b'c', 1, 2, b"lf"),
+                klv(b"FACE", b'?', 8, 1, &complex),
+            ],
+        );
+        let second = nested(
+            b"STRM",
+            &[
+                klv(b"TYPE", b'c', 1, 1, b"S"),
+                klv(b"KBAT", b'?', 2, 2, &[0, 1, 0, 2]),
+            ],
+        );
+        let res = parse_gpmf(&nested(b"DEVC", &[first.clone(), second])).unwrap();
+
+        let Value::Nested(streams) = res[0].value() else {
+            panic!("Expected nested value, got {:?}", res[0].value());
+        };
+        let Value::Nested(face) = streams[0].value() else {
+            panic!("Expected nested value, got {:?}", streams[0].value());
+        };
+        let Value::Complex(face) = face[1].value() else {
+            panic!("Expected complex value, got {:?}", face[1].value());
+        };
+        assert!(matches!(face[0][..], [Value::I32(7), Value::F32(f)] if f == 1.5));
+
+        let Value::Nested(kbat) = streams[1].value() else {
+            panic!("Expected nested value, got {:?}", streams[1].value());
+        };
+        let Value::Complex(kbat) = kbat[1].value() else {
+            panic!("Expected complex value, got {:?}", kbat[1].value());
+        };
+        assert_eq!(kbat.len(), 2);
+        assert!(matches!(kbat[0][..], [Value::U16(1)]));
+        assert!(matches!(kbat[1][..], [Value::U16(2)]));
+
+        // a sibling without its own TYPE must not pick up the previous one
+        let without_type = nested(b"STRM", &[klv(b"KBAT", b'?', 2, 2, &[0, 1, 0, 2])]);
+        let err = parse_gpmf(&nested(b"DEVC", &[first, without_type])).unwrap_err();
+        assert!(err.to_string().contains("no TYPE definition"), "{}", err);
+    }
+
+    #[test]
+    fn test_error_context() {
+        // GPS5 samples of 19 bytes, not a multiple of its 4 byte type
+        let mut text = std::fs::read(Path::new("samples").join("hero5.raw")).unwrap();
+        assert_eq!(&text[4068..4074], b"GPS5l\x14");
+        text[4073] = 19;
+        let err = parse_gpmf(&text).unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.starts_with("DEVC[0]/STRM[2]/GPS5 @ offset 0xFE4: "),
+            "{}",
+            message
+        );
+        assert!(message.contains("size of 19 bytes"), "{}", message);
+        assert!(matches!(
+            err.inner(),
+            GpmfError::InvalidSize {
+                tag: Tag::GPS5,
+                offset: 4068,
+                ..
+            }
+        ));
+        assert_eq!(err.offset(), Some(4068));
+        assert!(std::error::Error::source(&err).is_some());
+
+        // a top level entry has only its own tag
+        let err = parse_gpmf(&klv(b"ACCL", b's', 7, 1, &[0; 7])).unwrap_err();
+        assert!(
+            err.to_string().starts_with("ACCL @ offset 0x0: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_oversized_entry() {
+        // 65535 samples of 255 bytes in a 100 byte input
+        let mut data = klv(b"ACCL", b'B', 255, u16::MAX, &[]);
+        data.resize(100, 0);
+        let err = parse_gpmf_catching(&data).unwrap_err();
+        assert!(
+            matches!(
+                err.inner(),
+                GpmfError::EntryOverrun {
+                    tag: Tag::ACCL,
+                    declared: 16_711_425,
+                    remaining: 92,
+                    offset: 0
+                }
+            ),
+            "{:?}",
+            err
+        );
+        let message = err.to_string();
+        assert!(message.contains("ACCL at offset 0"), "{}", message);
+        assert!(message.contains("16711425"), "{}", message);
+        assert!(message.contains("only 92 remain"), "{}", message);
+    }
+
+    #[test]
+    fn test_truncated_nested() {
+        // DEVC declaring 16 bytes with only 12 present
+        let data = b"DEVC\x00\x01\x00\x10TSMPL\x04\x00\x01\x00\x00\x00\x01";
+        let err = parse_gpmf_catching(data).unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            GpmfError::NestedOverrun {
+                declared: 16,
+                remaining: 12,
+                ..
+            }
+        ));
+        let message = err.to_string();
+        assert!(message.contains("DEVC"), "{}", message);
+        assert!(message.contains("offset 0"), "{}", message);
+    }
+
+    #[test]
+    fn test_internal_error() {
+        let data = tsmp_entries(3);
+        let err = parse_catching(&data, &ParseOptions::default(), |input, state| {
+            let res = parse_entries(input, 0, 0, state);
+            panic!("Parsed {} entries", res.map_or(0, |res| res.len()));
+        })
+        .unwrap_err();
+        let GpmfError::Internal(internal) = &err else {
+            panic!("Expected an internal error, got {:?}", err);
+        };
+        assert_eq!(internal.message, "Parsed 3 entries");
+        // the last of the three entries
+        assert_eq!(internal.offset_hint, Some(24));
+        assert_eq!(err.offset(), Some(24));
+        assert!(err.to_string().contains("offset 24"), "{}", err);
+    }
+
+    #[test]
+    fn test_truncated_samples_never_panic() {
+        for name in ["hero5.raw", "karma.raw"] {
+            let text = std::fs::read(Path::new("samples").join(name)).unwrap();
+            for len in (0..text.len()).step_by(29) {
+                if let Err(err) = parse_gpmf_catching(&text[..len]) {
+                    assert!(
+                        !matches!(err.inner(), GpmfError::Internal(_)),
+                        "{} truncated to {} bytes: {}",
+                        name,
+                        len,
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    /// Build a flat stream of `count` TSMP entries, 12 bytes each
+    fn tsmp_entries(count: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..count {
+            data.extend_from_slice(b"TSMPL\x04\x00\x01");
+            data.extend_from_slice(&i.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_time_budget_zero() {
+        // the budget is checked before the first container
+        let data = [tsmp_entries(2), nested(b"DEVC", &[tsmp_entries(1)])].concat();
+        let options = ParseOptions {
+            time_budget: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let err = parse_gpmf_opts(&data, &options).unwrap_err();
+        let GpmfError::BudgetExceeded(exceeded) = err else {
+            panic!("Expected a budget error, got {:?}", err);
+        };
+        assert_eq!(exceeded.partial.len(), 2);
+        assert_eq!(exceeded.offset, 24);
+    }
+
+    #[test]
+    fn test_time_budget_partial() {
+        // without containers the budget is only checked every BUDGET_CHECK_INTERVAL entries
+        let count = 3 * BUDGET_CHECK_INTERVAL as u32;
+        let data = tsmp_entries(count);
+        let options = ParseOptions {
+            time_budget: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let err = parse_gpmf_opts(&data, &options).unwrap_err();
+        let GpmfError::BudgetExceeded(exceeded) = err else {
+            panic!("Expected a budget error, got {:?}", err);
+        };
+        assert_eq!(exceeded.partial.len(), BUDGET_CHECK_INTERVAL);
+        assert_eq!(exceeded.offset, BUDGET_CHECK_INTERVAL as u64 * 12);
+        exceeded
+            .partial
+            .iter()
+            .for_each(|kv| assert_eq!(kv.key, Tag::TSMP));
+    }
+
+    #[test]
+    fn test_time_budget_unlimited() {
+        let data = tsmp_entries(100);
+        let options = ParseOptions {
+            time_budget: Some(Duration::MAX),
+            ..Default::default()
+        };
+        let res = parse_gpmf_opts(&data, &options).unwrap();
+        assert_eq!(res.len(), 100);
+    }
 }
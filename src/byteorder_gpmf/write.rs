@@ -0,0 +1,1433 @@
+//! Writing GPMF streams, the inverse of [`parse_gpmf`](super::parse_gpmf)
+//!
+//! The headers are derived from the values: a sample of a [`Value::Simple`] becomes one repeat
+//! with a structure size of its elements, strings are written one byte per repeat
+//! and nested containers with a structure size of one byte when their length fits the repeat.
+//! The data of every entry is padded with NULs to 32 bits, and the length of a nested container
+//! includes the padding of its children, so it is a multiple of 4 as well.
+
+#[cfg(feature = "dates")]
+use crate::DATE_FORMAT;
+use crate::{Header, KeyValue, Tag, Type, Value};
+
+use std::fmt;
+use std::io::{self, Write};
+
+/// Errors returned by the writer
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WriteError {
+    /// Samples larger than the 255 bytes of the one byte structure size
+    SampleTooLarge {
+        /// The tag of the entry
+        tag: Tag,
+        /// The size of one sample in bytes
+        size: usize,
+    },
+    /// More samples than the 65535 of the 16 bit repeat
+    TooManySamples {
+        /// The tag of the entry
+        tag: Tag,
+        /// The number of samples
+        repeat: usize,
+    },
+    /// Samples that differ in the number or type of their elements
+    InconsistentSamples {
+        /// The tag of the entry
+        tag: Tag,
+        /// The index of the first sample that differs from the first one
+        sample: usize,
+    },
+    /// A value that can not be an element of a sample, e.g. a nested container or a char that is not ASCII
+    InvalidElement {
+        /// The tag of the entry
+        tag: Tag,
+    },
+    /// Complex samples with two strings in a row, which a TYPE definition can not tell apart
+    AdjacentStrings {
+        /// The tag of the entry
+        tag: Tag,
+    },
+    /// [`GpmfWriter::end`] without an open container
+    NoOpenContainer,
+    /// [`GpmfWriter::finish`] with a container that was not ended
+    UnclosedContainer {
+        /// The tag of the innermost open container
+        tag: Tag,
+    },
+    /// Writing to the output of a [`GpmfWriter`] failed
+    Io(io::Error),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::SampleTooLarge { tag, size } => write!(
+                f,
+                "{} has samples of {} bytes, more than the {} bytes of the structure size",
+                tag.fourcc(),
+                size,
+                u8::MAX
+            ),
+            WriteError::TooManySamples { tag, repeat } => write!(
+                f,
+                "{} has {} samples, more than the {} of the repeat",
+                tag.fourcc(),
+                repeat,
+                u16::MAX
+            ),
+            WriteError::InconsistentSamples { tag, sample } => write!(
+                f,
+                "Sample {} of {} differs in layout from the first sample",
+                sample,
+                tag.fourcc()
+            ),
+            WriteError::InvalidElement { tag } => {
+                write!(
+                    f,
+                    "{} has a sample element that can not be written",
+                    tag.fourcc()
+                )
+            }
+            WriteError::AdjacentStrings { tag } => write!(
+                f,
+                "{} has two strings in a row, which can not be described by a TYPE",
+                tag.fourcc()
+            ),
+            WriteError::NoOpenContainer => write!(f, "No open container to end"),
+            WriteError::UnclosedContainer { tag } => {
+                write!(f, "{} was not ended before finishing", tag.fourcc())
+            }
+            WriteError::Io(err) => write!(f, "Writing the output failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WriteError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WriteError {
+    fn from(err: io::Error) -> Self {
+        WriteError::Io(err)
+    }
+}
+
+/// Options controlling how the entries are written
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Write parsed entries with the header, data bytes and padding they were read with where they were kept,
+    /// see [`ParseOptions::exact`](super::ParseOptions::exact).
+    ///
+    /// The original header is used when it describes as many data bytes as are written,
+    /// e.g. a nested container with a structure size of 4 bytes.
+    pub exact: bool,
+    /// Write samples that do not fit the 16 bit repeat as several consecutive entries with the same key,
+    /// instead of failing with [`WriteError::TooManySamples`]. Applies to [`Value::Simple`], [`Value::Complex`]
+    /// and [`Value::Strings`], parsing the output gives one entry per 65535 samples
+    pub split_repeats: bool,
+}
+
+/// Write the entries as a GPMF stream
+///
+/// The strings and TYPE definitions of parsed entries are written as they were read,
+/// so parsing the output gives the same values.
+/// Complex data is preceded by a TYPE derived from its samples unless the last TYPE of its container describes it.
+///
+/// # Errors
+///
+/// Returns an error if an entry does not fit a KLV header or its samples differ in layout
+pub fn write_gpmf(entries: &[KeyValue]) -> Result<Vec<u8>, WriteError> {
+    write_gpmf_opts(entries, &WriteOptions::default())
+}
+
+/// Write the entries as a GPMF stream using the given [`WriteOptions`]
+///
+/// # Errors
+///
+/// Returns the same errors as [`write_gpmf`]
+pub fn write_gpmf_opts(
+    entries: &[KeyValue],
+    options: &WriteOptions,
+) -> Result<Vec<u8>, WriteError> {
+    let mut out = Vec::new();
+    write_entries(&mut out, entries, options)?;
+    Ok(out)
+}
+
+/// Writes a GPMF stream to any [`Write`] entry by entry, e.g. hours of generated telemetry
+///
+/// Top level entries are written as soon as they are complete. The children of an open container are
+/// buffered until it is ended, when its length is known, so only the open containers are held in memory.
+/// The output is the same as [`write_gpmf`] for the same entries.
+///
+/// ```
+/// use gpmf::byteorder_gpmf::GpmfWriter;
+/// use gpmf::{Tag, Value};
+///
+/// let mut writer = GpmfWriter::new(Vec::new());
+/// writer.begin_device(1, "Camera")?;
+/// writer.begin_stream("Accelerometer")?;
+/// writer.write_entry(Tag::SCAL, &Value::Simple(vec![vec![Value::I16(418)]]))?;
+/// writer.write_entry(
+///     Tag::ACCL,
+///     &Value::Simple(vec![vec![Value::I16(1), Value::I16(2), Value::I16(3)]]),
+/// )?;
+/// writer.end()?;
+/// writer.end()?;
+/// let bytes = writer.finish()?;
+/// assert_eq!(&bytes[..4], b"DEVC");
+/// # Ok::<(), gpmf::byteorder_gpmf::WriteError>(())
+/// ```
+#[derive(Debug)]
+pub struct GpmfWriter<W: Write> {
+    /// The output
+    inner: W,
+    /// The open containers, innermost last
+    open: Vec<Frame>,
+    /// The top level TYPE definition
+    type_def: Option<Vec<Type>>,
+    /// A top level entry being encoded
+    buf: Vec<u8>,
+    /// The options for the entries
+    options: WriteOptions,
+}
+
+/// A container of a [`GpmfWriter`] that is being written
+#[derive(Debug)]
+struct Frame {
+    /// The FourCC key of the container
+    key: Tag,
+    /// The children written so far
+    data: Vec<u8>,
+    /// The TYPE definition for the complex children
+    type_def: Option<Vec<Type>>,
+}
+
+impl<W: Write> GpmfWriter<W> {
+    /// A writer to `inner`
+    pub fn new(inner: W) -> Self {
+        Self::with_options(inner, WriteOptions::default())
+    }
+
+    /// A writer to `inner` using the given [`WriteOptions`]
+    pub fn with_options(inner: W, options: WriteOptions) -> Self {
+        GpmfWriter {
+            inner,
+            open: Vec::new(),
+            type_def: None,
+            buf: Vec::new(),
+            options,
+        }
+    }
+
+    /// Open a container, its entries are written until the matching [`GpmfWriter::end`]
+    pub fn begin(&mut self, key: Tag) {
+        self.open.push(Frame {
+            key,
+            data: Vec::new(),
+            type_def: None,
+        });
+    }
+
+    /// Open a DEVC container starting with its DVID and DVNM
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is longer than 65535 bytes
+    pub fn begin_device(&mut self, id: u32, name: &str) -> Result<(), WriteError> {
+        self.begin(Tag::DEVC);
+        self.write_entry(Tag::DVID, &Value::Simple(vec![vec![Value::U32(id)]]))?;
+        self.write_entry(Tag::DVNM, &Value::String(name.to_string()))
+    }
+
+    /// Open a STRM container starting with its STNM
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is longer than 65535 bytes
+    pub fn begin_stream(&mut self, name: &str) -> Result<(), WriteError> {
+        self.begin(Tag::STRM);
+        self.write_entry(Tag::STNM, &Value::String(name.to_string()))
+    }
+
+    /// Write an entry into the innermost open container, or to the output at the top level
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`write_gpmf`], or [`WriteError::Io`] if writing to the output fails
+    pub fn write_entry(&mut self, key: Tag, value: &Value) -> Result<(), WriteError> {
+        let (out, type_def) = match self.open.last_mut() {
+            Some(frame) => (&mut frame.data, &mut frame.type_def),
+            None => (&mut self.buf, &mut self.type_def),
+        };
+        let entry = EntryRef {
+            key: &key,
+            value,
+            kept: None,
+        };
+        let start = out.len();
+        let previous = type_def.clone();
+        let written = write_in_container(out, entry, type_def, &self.options);
+        if written.is_err() {
+            // drop what was written of the entry, including a TYPE written for it
+            out.truncate(start);
+            *type_def = previous;
+        } else if self.open.is_empty() {
+            self.inner.write_all(&self.buf)?;
+        }
+        self.buf.clear();
+        written
+    }
+
+    /// Close the innermost open container
+    ///
+    /// # Errors
+    ///
+    /// Returns [`WriteError::NoOpenContainer`] if there is none, or an error if the container is too long
+    /// or writing to the output fails
+    pub fn end(&mut self) -> Result<(), WriteError> {
+        let frame = self.open.pop().ok_or(WriteError::NoOpenContainer)?;
+        let (type_u8, size, repeat) = nested_header(frame.data.len());
+        let header = header_bytes(&frame.key, type_u8, size, repeat)?;
+        match self.open.last_mut() {
+            Some(parent) => {
+                parent.data.extend_from_slice(&header);
+                parent.data.extend_from_slice(&frame.data);
+            }
+            None => {
+                self.inner.write_all(&header)?;
+                self.inner.write_all(&frame.data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the output and return it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`WriteError::UnclosedContainer`] if a container was not ended, or an error if flushing fails
+    pub fn finish(mut self) -> Result<W, WriteError> {
+        if let Some(frame) = self.open.pop() {
+            return Err(WriteError::UnclosedContainer { tag: frame.key });
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Append the entries to `out`, copying the bytes of the entries that have not changed since they were read from `source`
+///
+/// The containers with changed children are written with the new length of their children.
+pub(crate) fn write_spliced(
+    out: &mut Vec<u8>,
+    entries: &[KeyValue],
+    source: &[u8],
+) -> Result<(), WriteError> {
+    let mut type_def = None;
+    for kv in entries {
+        let read = kv.span.and_then(|(offset, len)| {
+            let offset = usize::try_from(offset).ok()?;
+            source.get(offset..offset.checked_add(len)?)
+        });
+        match (read, &kv.value) {
+            (Some(bytes), value) => {
+                if let Value::Type(types) = value {
+                    type_def = Some(types.clone());
+                }
+                out.extend_from_slice(bytes);
+            }
+            (None, Value::Nested(children)) => {
+                let header_at = out.len();
+                //the header is filled in once the children are written
+                out.extend_from_slice(&[0; 8]);
+                write_spliced(out, children, source)?;
+                let len = out.len() - header_at - 8;
+                let (type_u8, size, repeat) = nested_header(len);
+                out[header_at..header_at + 8]
+                    .copy_from_slice(&header_bytes(&kv.key, type_u8, size, repeat)?);
+            }
+            (None, _) => write_in_container(
+                out,
+                EntryRef::from(kv),
+                &mut type_def,
+                &WriteOptions::default(),
+            )?,
+        }
+    }
+    Ok(())
+}
+
+/// An entry to write, a key and value with what was kept when it was parsed
+#[derive(Debug, Clone, Copy)]
+struct EntryRef<'a> {
+    /// The FourCC key
+    key: &'a Tag,
+    /// The value
+    value: &'a Value,
+    /// The parsed entry, `None` for a key and value on their own
+    kept: Option<&'a KeyValue>,
+}
+
+impl<'a> From<&'a KeyValue> for EntryRef<'a> {
+    fn from(kv: &'a KeyValue) -> Self {
+        EntryRef {
+            key: &kv.key,
+            value: &kv.value,
+            kept: Some(kv),
+        }
+    }
+}
+
+impl<'a> EntryRef<'a> {
+    /// The header the entry was read with
+    fn header(&self) -> Option<Header> {
+        self.kept.and_then(|kv| kv.header)
+    }
+
+    /// The data bytes the entry was read with
+    fn raw(&self) -> Option<&'a [u8]> {
+        self.kept.and_then(|kv| kv.raw.as_deref())
+    }
+
+    /// The padding bytes the entry was read with
+    fn padding(&self) -> Option<&'a [u8]> {
+        self.kept.and_then(|kv| kv.padding.as_deref())
+    }
+}
+
+/// Append the KLV entries of one nesting level to `out`
+fn write_entries(
+    out: &mut Vec<u8>,
+    entries: &[KeyValue],
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    let mut type_def = None;
+    for kv in entries {
+        write_in_container(out, EntryRef::from(kv), &mut type_def, options)?;
+    }
+    Ok(())
+}
+
+/// Append one KLV entry of a container to `out`, keeping track of the container's TYPE definition
+///
+/// Complex data the current TYPE does not describe is preceded by a TYPE derived from its samples.
+fn write_in_container(
+    out: &mut Vec<u8>,
+    entry: EntryRef<'_>,
+    type_def: &mut Option<Vec<Type>>,
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    //like when parsing, a TYPE applies to the Complex entries after it in the same container
+    match entry.value {
+        Value::Type(types) => *type_def = Some(types.clone()),
+        Value::Complex(samples) => {
+            if let Some(types) = complex_type_def(entry.key, samples, type_def.as_deref())? {
+                let type_entry = EntryRef {
+                    key: &Tag::TYPE,
+                    value: &Value::Type(types.clone()),
+                    kept: None,
+                };
+                write_entry(out, type_entry, None, options)?;
+                *type_def = Some(types);
+            }
+        }
+        _ => {}
+    }
+    match split_samples(entry.value).filter(|_| options.split_repeats) {
+        Some(parts) => {
+            for value in &parts {
+                let part = EntryRef {
+                    key: entry.key,
+                    value,
+                    kept: None,
+                };
+                write_entry(out, part, type_def.as_deref(), options)?;
+            }
+            Ok(())
+        }
+        None => write_entry(out, entry, type_def.as_deref(), options),
+    }
+}
+
+/// The samples of a value split into values of at most 65535 samples, `None` if they fit one entry
+fn split_samples(value: &Value) -> Option<Vec<Value>> {
+    let max = u16::MAX as usize;
+    match value {
+        Value::Simple(samples) if samples.len() > max => Some(
+            samples
+                .chunks(max)
+                .map(|part| Value::Simple(part.to_vec()))
+                .collect(),
+        ),
+        Value::Complex(samples) if samples.len() > max => Some(
+            samples
+                .chunks(max)
+                .map(|part| Value::Complex(part.to_vec()))
+                .collect(),
+        ),
+        Value::Strings(strings) if strings.len() > max => Some(
+            strings
+                .chunks(max)
+                .map(|part| Value::Strings(part.to_vec()))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// The TYPE definition for complex samples when `type_def` does not describe them, `None` when it does
+///
+/// Each string field of the derived definition is as wide as its longest string.
+fn complex_type_def(
+    tag: &Tag,
+    samples: &[Vec<Value>],
+    type_def: Option<&[Type]>,
+) -> Result<Option<Vec<Type>>, WriteError> {
+    let Some(first) = samples.first() else {
+        return Ok(None);
+    };
+    let fields = first
+        .iter()
+        .map(complex_field_type)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| WriteError::InvalidElement { tag: tag.clone() })?;
+    // a run of characters is a single string, so two strings in a row can not be told apart
+    if fields
+        .windows(2)
+        .any(|pair| pair[0] == Type::Char && pair[1] == Type::Char)
+    {
+        return Err(WriteError::AdjacentStrings { tag: tag.clone() });
+    }
+
+    let mut widths = vec![1; fields.len()];
+    for (i, sample) in samples.iter().enumerate() {
+        if sample.len() != fields.len()
+            || sample
+                .iter()
+                .zip(&fields)
+                .any(|(value, field)| complex_field_type(value) != Some(*field))
+        {
+            return Err(WriteError::InconsistentSamples {
+                tag: tag.clone(),
+                sample: i,
+            });
+        }
+        for (value, width) in sample.iter().zip(&mut widths) {
+            if let Value::String(string) = value {
+                *width = string.len().max(*width);
+            }
+        }
+    }
+
+    let described = type_def.is_some_and(|type_def| {
+        let runs: Vec<_> = type_def
+            .chunk_by(|a, b| *a == Type::Char && *b == Type::Char)
+            .collect();
+        runs.len() == fields.len()
+            && runs.iter().zip(fields.iter().zip(&widths)).all(
+                |(run, (field, width))| match field {
+                    Type::Char => run[0] == Type::Char && run.len() >= *width,
+                    field => run == &[*field],
+                },
+            )
+    });
+    if described {
+        return Ok(None);
+    }
+    let derived = fields
+        .iter()
+        .zip(&widths)
+        .flat_map(|(field, width)| {
+            let count = if *field == Type::Char { *width } else { 1 };
+            std::iter::repeat_n(*field, count)
+        })
+        .collect();
+    Ok(Some(derived))
+}
+
+/// The type of a field of a complex sample, a string is a run of characters
+fn complex_field_type(value: &Value) -> Option<Type> {
+    match value {
+        Value::Char(_) => None,
+        value => element_type(value),
+    }
+}
+
+/// Append one KLV entry including its padding to `out`
+fn write_entry(
+    out: &mut Vec<u8>,
+    entry: EntryRef<'_>,
+    type_def: Option<&[Type]>,
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    let header_at = out.len();
+    //the header is filled in once the data is written
+    out.extend_from_slice(&[0; 8]);
+    let data_at = out.len();
+    let original = entry.header().filter(|_| options.exact);
+    let raw = entry.raw().filter(|raw| {
+        !matches!(entry.value, Value::Nested(_))
+            && original.is_some_and(|header| header.data_len() == raw.len())
+    });
+    let (type_u8, size, repeat) = match (original, raw) {
+        // the data as it was read, e.g. the bytes after the NUL of a complex string
+        (Some(header), Some(raw)) => {
+            out.extend_from_slice(raw);
+            (header.type_u8, header.size as usize, header.repeat as usize)
+        }
+        _ => match (entry.header(), write_data(out, entry, type_def, options)?) {
+            (Some(header), _) if options.exact && header.data_len() == out.len() - data_at => {
+                (header.type_u8, header.size as usize, header.repeat as usize)
+            }
+            // a date some firmwares write as a string, e.g. GPSU, keeps its type letter
+            (Some(header), (type_u8, ..))
+                if type_u8 == Type::Date as u8
+                    && header.type_u8 == Type::Char as u8
+                    && header.data_len() == out.len() - data_at =>
+            {
+                (header.type_u8, header.size as usize, header.repeat as usize)
+            }
+            (_, derived) => derived,
+        },
+    };
+
+    out[header_at..data_at].copy_from_slice(&header_bytes(entry.key, type_u8, size, repeat)?);
+
+    match entry.padding().filter(|_| options.exact) {
+        Some(padding) => out.extend_from_slice(padding),
+        None => pad(out, out.len() - data_at),
+    }
+    Ok(())
+}
+
+/// The key and header of an entry, checking that the structure size and repeat fit
+fn header_bytes(key: &Tag, type_u8: u8, size: usize, repeat: usize) -> Result<[u8; 8], WriteError> {
+    let size = u8::try_from(size).map_err(|_| WriteError::SampleTooLarge {
+        tag: key.clone(),
+        size,
+    })?;
+    let repeat = u16::try_from(repeat).map_err(|_| WriteError::TooManySamples {
+        tag: key.clone(),
+        repeat,
+    })?;
+    let mut header = [0; 8];
+    header[..4].copy_from_slice(&key.fourcc_bytes());
+    header[4] = type_u8;
+    header[5] = size;
+    header[6..].copy_from_slice(&repeat.to_be_bytes());
+    Ok(header)
+}
+
+/// The type letter, structure size and repeat of a nested container with `len` bytes of children
+fn nested_header(len: usize) -> (u8, usize, usize) {
+    let (size, repeat) = packing(len);
+    (Type::Nested as u8, size, repeat)
+}
+
+/// The structure size and repeat of `len` bytes without samples of their own, like nested containers
+///
+/// The structure size is the smallest that gives a repeat that fits, usually 1 byte.
+/// Without one the repeat is too large for the header.
+fn packing(len: usize) -> (usize, usize) {
+    let size = (1..=u8::MAX as usize)
+        .find(|size| len.is_multiple_of(*size) && len / size <= u16::MAX as usize)
+        .unwrap_or(1);
+    (size, len / size)
+}
+
+/// Append the NUL padding that aligns `len` data bytes to 32 bits
+fn pad(out: &mut Vec<u8>, len: usize) {
+    out.resize(out.len() + (4 - len % 4) % 4, 0);
+}
+
+/// Append the data of an entry to `out`, returning the type letter, structure size and repeat of its header
+fn write_data(
+    out: &mut Vec<u8>,
+    entry: EntryRef<'_>,
+    type_def: Option<&[Type]>,
+    options: &WriteOptions,
+) -> Result<(u8, usize, usize), WriteError> {
+    let data_at = out.len();
+    let header = match entry.value {
+        Value::Nested(children) => {
+            write_entries(out, children, options)?;
+            nested_header(out.len() - data_at)
+        }
+        Value::Simple(samples) => {
+            let typ = samples
+                .first()
+                .and_then(|sample| sample.first())
+                .and_then(element_type)
+                .unwrap_or(Type::U8);
+            let width = samples.first().map_or(0, Vec::len);
+            // the size is the bytes written per sample, a string element is as wide as the string
+            let mut size = None;
+            for (i, sample) in samples.iter().enumerate() {
+                let sample_at = out.len();
+                let consistent =
+                    sample.len() == width && sample.iter().all(|v| element_type(v) == Some(typ));
+                if consistent {
+                    for value in sample {
+                        write_element(out, value, None, entry.key)?;
+                    }
+                }
+                let sample_size = out.len() - sample_at;
+                if !consistent || *size.get_or_insert(sample_size) != sample_size {
+                    return Err(WriteError::InconsistentSamples {
+                        tag: entry.key.clone(),
+                        sample: i,
+                    });
+                }
+            }
+            (typ as u8, size.unwrap_or(width * typ.size()), samples.len())
+        }
+        Value::String(string) => {
+            let bytes = entry.raw().unwrap_or(string.as_bytes());
+            out.extend_from_slice(bytes);
+            (Type::Char as u8, 1, bytes.len())
+        }
+        Value::Strings(strings) => match entry.kept.and_then(KeyValue::raw_strings) {
+            Some(mut fields) => {
+                let width = fields.next().map_or(1, <[u8]>::len);
+                out.extend_from_slice(entry.raw().unwrap_or_default());
+                (Type::Char as u8, width, strings.len())
+            }
+            None => {
+                let width = strings.iter().map(String::len).max().unwrap_or(0).max(1);
+                for string in strings {
+                    write_string(out, string, width);
+                }
+                (Type::Char as u8, width, strings.len())
+            }
+        },
+        Value::Type(types) => {
+            match entry.raw() {
+                Some(raw) => out.extend_from_slice(raw),
+                None => out.extend(types.iter().map(|t| *t as u8)),
+            }
+            (Type::Char as u8, 1, out.len() - data_at)
+        }
+        Value::Complex(samples) => {
+            let mut size = None;
+            for (i, sample) in samples.iter().enumerate() {
+                let sample_at = out.len();
+                write_complex_sample(out, sample, type_def, entry.key)?;
+                let sample_size = out.len() - sample_at;
+                if *size.get_or_insert(sample_size) != sample_size {
+                    return Err(WriteError::InconsistentSamples {
+                        tag: entry.key.clone(),
+                        sample: i,
+                    });
+                }
+            }
+            (Type::Complex as u8, size.unwrap_or(0), samples.len())
+        }
+        Value::Empty(typ) => {
+            // an empty entry still declares its structure size, e.g. FACE when no faces were found
+            let size = match (entry.header(), typ) {
+                (Some(header), _) => header.size as usize,
+                (None, Type::Complex) => {
+                    type_def.map_or(0, |def| def.iter().map(|t| t.size()).sum())
+                }
+                (None, Type::Nested) => 0,
+                (None, typ) => typ.size(),
+            };
+            (*typ as u8, size, 0)
+        }
+        Value::Raw { type_char, bytes } => {
+            out.extend_from_slice(bytes);
+            // the samples of an unknown type are only known from the header it was read with
+            let (size, repeat) = match entry.header() {
+                Some(header) if header.data_len() == bytes.len() => {
+                    (header.size as usize, header.repeat as usize)
+                }
+                _ => packing(bytes.len()),
+            };
+            (*type_char, size, repeat)
+        }
+        // a single element is an entry with one sample of one element
+        value => {
+            let typ = element_type(value).ok_or_else(|| WriteError::InvalidElement {
+                tag: entry.key.clone(),
+            })?;
+            write_element(out, value, None, entry.key)?;
+            (typ as u8, out.len() - data_at, 1)
+        }
+    };
+    Ok(header)
+}
+
+/// Append one sample of a complex entry, with each run of characters in the TYPE as a fixed length string
+fn write_complex_sample(
+    out: &mut Vec<u8>,
+    sample: &[Value],
+    type_def: Option<&[Type]>,
+    tag: &Tag,
+) -> Result<(), WriteError> {
+    let mut fields = type_def
+        .into_iter()
+        .flat_map(|def| def.chunk_by(|a, b| *a == Type::Char && *b == Type::Char));
+    for value in sample {
+        let width = fields
+            .next()
+            .filter(|field| field[0] == Type::Char)
+            .map(<[Type]>::len);
+        write_element(out, value, width, tag)?;
+    }
+    Ok(())
+}
+
+/// The type of a value that can be an element of a sample
+fn element_type(value: &Value) -> Option<Type> {
+    match value {
+        Value::Nested(_)
+        | Value::Simple(_)
+        | Value::Complex(_)
+        | Value::Strings(_)
+        | Value::Type(_)
+        | Value::Empty(_)
+        | Value::Raw { .. } => None,
+        Value::I8(_)
+        | Value::U8(_)
+        | Value::Char(_)
+        | Value::String(_)
+        | Value::F64(_)
+        | Value::F32(_)
+        | Value::Tag(_)
+        | Value::U128(_)
+        | Value::I64(_)
+        | Value::U64(_)
+        | Value::I32(_)
+        | Value::U32(_)
+        | Value::Fixed32(_)
+        | Value::Fixed64(_)
+        | Value::I16(_)
+        | Value::U16(_)
+        | Value::Date(_)
+        | Value::RawDate(_)
+        | Value::NoDate(_) => value.datatype(),
+    }
+}
+
+/// Append a single element in big endian, a string padded with NULs to `width` if given
+fn write_element(
+    out: &mut Vec<u8>,
+    value: &Value,
+    width: Option<usize>,
+    tag: &Tag,
+) -> Result<(), WriteError> {
+    match value {
+        Value::I8(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::U8(v) => out.push(*v),
+        // a char is a single byte, anything but ASCII would not be read back as the same char
+        Value::Char(c) if c.is_ascii() => out.push(*c as u8),
+        Value::Char(_) => return Err(WriteError::InvalidElement { tag: tag.clone() }),
+        Value::String(s) => write_string(out, s, width.unwrap_or(s.len())),
+        Value::F64(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::F32(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::Tag(tag) => out.extend_from_slice(&tag.fourcc_bytes()),
+        Value::U128(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::I64(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::U64(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::I32(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::U32(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::Fixed32(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::Fixed64(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::I16(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Value::U16(v) => out.extend_from_slice(&v.to_be_bytes()),
+        #[cfg(feature = "dates")]
+        Value::Date(date) => write_string(out, &date.format(DATE_FORMAT).to_string(), 16),
+        #[cfg(not(feature = "dates"))]
+        Value::Date(bytes) => out.extend_from_slice(bytes),
+        Value::RawDate(bytes) | Value::NoDate(bytes) => out.extend_from_slice(bytes),
+        Value::Complex(_)
+        | Value::Nested(_)
+        | Value::Simple(_)
+        | Value::Type(_)
+        | Value::Strings(_)
+        | Value::Empty(_)
+        | Value::Raw { .. } => return Err(WriteError::InvalidElement { tag: tag.clone() }),
+    }
+    Ok(())
+}
+
+/// Append a string padded with NULs or cut to `width` bytes
+fn write_string(out: &mut Vec<u8>, string: &str, width: usize) {
+    let bytes = string.as_bytes();
+    let len = bytes.len().min(width);
+    out.extend_from_slice(&bytes[..len]);
+    out.resize(out.len() + width - len, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::GpmfBuilder;
+    use crate::byteorder_gpmf::{parse_gpmf, parse_gpmf_opts, ParseOptions};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::path::Path;
+
+    #[test]
+    fn test_roundtrip_samples() {
+        for name in [
+            "hero5.raw",
+            "hero6.raw",
+            "hero6+ble.raw",
+            "Fusion.raw",
+            "karma.raw",
+        ] {
+            let text = std::fs::read(Path::new("samples").join(name)).unwrap();
+            let written = write_gpmf(&parse_gpmf(&text).unwrap()).unwrap();
+            let first_difference = text.iter().zip(&written).position(|(a, b)| a != b);
+            assert_eq!(first_difference, None, "{}", name);
+            assert_eq!(written.len(), text.len(), "{}", name);
+        }
+    }
+
+    /// Check that every entry starts on a 32 bit boundary, and that every nested container has a length
+    /// that is a multiple of 4 and holds exactly its children
+    fn check_alignment(bytes: &[u8]) {
+        let mut offset = 0;
+        while offset < bytes.len() {
+            assert!(offset.is_multiple_of(4), "Entry at offset {}", offset);
+            let header = &bytes[offset..offset + 8];
+            let len = header[5] as usize * u16::from_be_bytes([header[6], header[7]]) as usize;
+            if header[4] == 0 {
+                assert!(
+                    len.is_multiple_of(4),
+                    "Nested length {} at offset {}",
+                    len,
+                    offset
+                );
+                check_alignment(&bytes[offset + 8..offset + 8 + len]);
+            }
+            offset += 8 + len.next_multiple_of(4);
+        }
+        assert_eq!(offset, bytes.len());
+    }
+
+    #[test]
+    fn test_alignment() {
+        for name in [
+            "hero5.raw",
+            "hero6.raw",
+            "hero6+ble.raw",
+            "Fusion.raw",
+            "karma.raw",
+        ] {
+            let text = std::fs::read(Path::new("samples").join(name)).unwrap();
+            let entries = without_kept(&parse_gpmf(&text).unwrap());
+            let written = write_gpmf(&entries).unwrap();
+            check_alignment(&written);
+            parse_gpmf(&written).unwrap();
+        }
+
+        // data lengths of 1, 2 and 3 bytes in nested containers
+        let entries = vec![KeyValue::new(
+            Tag::DEVC,
+            Value::Nested(vec![
+                KeyValue::new(Tag::DVNM, Value::String("A".to_string())),
+                KeyValue::new(
+                    Tag::STRM,
+                    Value::Nested(vec![
+                        KeyValue::new(Tag::STNM, Value::String("AB".to_string())),
+                        KeyValue::new(
+                            Tag::ISOG,
+                            Value::Simple(vec![vec![Value::U8(1), Value::U8(2), Value::U8(3)]]),
+                        ),
+                        KeyValue::new(
+                            Tag::from_fourcc(*b"ABCD"),
+                            Value::Raw {
+                                type_char: b'x',
+                                bytes: vec![1, 2, 3],
+                            },
+                        ),
+                    ]),
+                ),
+                KeyValue::new(Tag::STRM, Value::Nested(Vec::new())),
+            ]),
+        )];
+        let written = write_gpmf(&entries).unwrap();
+        check_alignment(&written);
+        let parsed = parse_gpmf(&written).unwrap();
+        assert_eq!(write_gpmf(&parsed).unwrap(), written);
+
+        let payload = GpmfBuilder::device(1, "Odd")
+            .stream("Odd", |s| {
+                s.units("m").samples_i16(Tag::ACCL, &[[1_i16, 2, 3]])
+            })
+            .to_bytes()
+            .unwrap();
+        check_alignment(&payload);
+        parse_gpmf(&payload).unwrap();
+    }
+
+    /// Hash bytes with the standard library hasher
+    fn hash(bytes: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_exact_roundtrip_samples() {
+        let parse_options = ParseOptions {
+            exact: true,
+            ..Default::default()
+        };
+        let write_options = WriteOptions {
+            exact: true,
+            ..Default::default()
+        };
+        for name in [
+            "hero5.raw",
+            "hero6.raw",
+            "hero6+ble.raw",
+            "Fusion.raw",
+            "karma.raw",
+        ] {
+            let text = std::fs::read(Path::new("samples").join(name)).unwrap();
+            let entries = parse_gpmf_opts(&text, &parse_options).unwrap();
+            let written = write_gpmf_opts(&entries, &write_options).unwrap();
+            assert_eq!(hash(&written), hash(&text), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_exact_packing() {
+        let text = [
+            // a container with a structure size of 4 bytes
+            b"DEVC\0\x04\0\x0B".as_slice(),
+            // a string padded with garbage instead of NULs
+            b"DVNMc\x01\0\x06Camera\xAA\xBB",
+            b"TYPEc\x01\0\x03ccL\0",
+            // a two character string with a byte after its NUL
+            b"FACE?\x06\0\x01\0\xEE\0\0\0\x01\0\0",
+        ]
+        .concat();
+        let entries = parse_gpmf(&text).unwrap();
+        assert_ne!(write_gpmf(&entries).unwrap(), text);
+
+        let exact = parse_gpmf_opts(
+            &text,
+            &ParseOptions {
+                exact: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let write_options = WriteOptions {
+            exact: true,
+            ..Default::default()
+        };
+        let written = write_gpmf_opts(&exact, &write_options).unwrap();
+        assert_eq!(written, text);
+        // without the kept bytes only the headers are preserved
+        let written = write_gpmf_opts(&entries, &write_options).unwrap();
+        assert_eq!(&written[..8], &text[..8]);
+        assert_ne!(written, text);
+    }
+
+    #[test]
+    fn test_write_values() {
+        let entries = vec![KeyValue::new(
+            Tag::DEVC,
+            Value::Nested(vec![
+                KeyValue::new(Tag::DVNM, Value::String("Camera".to_string())),
+                KeyValue::new(
+                    Tag::STRM,
+                    Value::Nested(vec![
+                        KeyValue::new(Tag::SCAL, Value::Simple(vec![vec![Value::I16(418)]])),
+                        KeyValue::new(
+                            Tag::ACCL,
+                            Value::Simple(vec![
+                                vec![Value::I16(1), Value::I16(-2), Value::I16(3)],
+                                vec![Value::I16(4), Value::I16(5), Value::I16(-6)],
+                            ]),
+                        ),
+                    ]),
+                ),
+            ]),
+        )];
+        let written = write_gpmf(&entries).unwrap();
+        assert_eq!(
+            written,
+            [
+                b"DEVC\0\x01\0\x38".as_slice(),
+                b"DVNMc\x01\0\x06Camera\0\0",
+                b"STRM\0\x01\0\x20",
+                b"SCALs\x02\0\x01\x01\xA2\0\0",
+                b"ACCLs\x06\0\x02\0\x01\xFF\xFE\0\x03\0\x04\0\x05\xFF\xFA",
+            ]
+            .concat()
+        );
+
+        let parsed = parse_gpmf(&written).unwrap();
+        assert_eq!(write_gpmf(&parsed).unwrap(), written);
+    }
+
+    /// Write the entries with a [`GpmfWriter`], opening a container for each nested value
+    fn stream_entries<W: Write>(
+        writer: &mut GpmfWriter<W>,
+        entries: &[KeyValue],
+    ) -> Result<(), WriteError> {
+        for kv in entries {
+            match kv.value() {
+                Value::Nested(children) => {
+                    writer.begin(kv.key().clone());
+                    stream_entries(writer, children)?;
+                    writer.end()?;
+                }
+                value => writer.write_entry(kv.key().clone(), value)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// The entries without what was kept when parsing them, like entries built by hand
+    fn without_kept(entries: &[KeyValue]) -> Vec<KeyValue> {
+        entries
+            .iter()
+            .map(|kv| match kv.value() {
+                Value::Nested(children) => {
+                    KeyValue::new(kv.key().clone(), Value::Nested(without_kept(children)))
+                }
+                value => KeyValue::new(kv.key().clone(), value.clone()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_streaming_writer() {
+        for name in [
+            "hero5.raw",
+            "hero6.raw",
+            "hero6+ble.raw",
+            "Fusion.raw",
+            "karma.raw",
+        ] {
+            let text = std::fs::read(Path::new("samples").join(name)).unwrap();
+            let entries = without_kept(&parse_gpmf(&text).unwrap());
+            let mut writer = GpmfWriter::new(Vec::new());
+            stream_entries(&mut writer, &entries).unwrap();
+            assert_eq!(
+                writer.finish().unwrap(),
+                write_gpmf(&entries).unwrap(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_streaming_device() {
+        let mut writer = GpmfWriter::new(Vec::new());
+        writer.begin_device(1, "Camera").unwrap();
+        writer.begin_stream("Accelerometer").unwrap();
+        let accl = Value::Simple(vec![vec![Value::I16(1), Value::I16(2), Value::I16(3)]]);
+        writer.write_entry(Tag::ACCL, &accl).unwrap();
+        writer.end().unwrap();
+        writer.end().unwrap();
+
+        let entries = vec![KeyValue::new(
+            Tag::DEVC,
+            Value::Nested(vec![
+                KeyValue::new(Tag::DVID, Value::Simple(vec![vec![Value::U32(1)]])),
+                KeyValue::new(Tag::DVNM, Value::String("Camera".to_string())),
+                KeyValue::new(
+                    Tag::STRM,
+                    Value::Nested(vec![
+                        KeyValue::new(Tag::STNM, Value::String("Accelerometer".to_string())),
+                        KeyValue::new(Tag::ACCL, accl),
+                    ]),
+                ),
+            ]),
+        )];
+        assert_eq!(writer.finish().unwrap(), write_gpmf(&entries).unwrap());
+    }
+
+    #[test]
+    fn test_streaming_errors() {
+        let mut writer = GpmfWriter::new(Vec::new());
+        assert!(matches!(writer.end(), Err(WriteError::NoOpenContainer)));
+
+        // a failed entry leaves nothing behind
+        let wide = Value::Simple(vec![vec![Value::F64(0.0); 32]]);
+        assert!(writer.write_entry(Tag::ACCL, &wide).is_err());
+        writer.begin_stream("Accelerometer").unwrap();
+        assert!(writer.write_entry(Tag::ACCL, &wide).is_err());
+        writer.end().unwrap();
+        let stream = writer.finish().unwrap();
+        assert_eq!(
+            stream,
+            b"STRM\0\x01\0\x18STNMc\x01\0\x0DAccelerometer\0\0\0"
+        );
+
+        let mut writer = GpmfWriter::new(Vec::new());
+        writer.begin_device(1, "Camera").unwrap();
+        assert!(matches!(
+            writer.finish(),
+            Err(WriteError::UnclosedContainer { tag: Tag::DEVC })
+        ));
+    }
+
+    /// A FACE-like complex sample of an ID, a name and a box
+    fn face(id: u32, name: &str, x: f32) -> Vec<Value> {
+        vec![
+            Value::U32(id),
+            Value::String(name.to_string()),
+            Value::F32(x),
+            Value::F32(0.25),
+            Value::F32(0.5),
+            Value::F32(0.5),
+        ]
+    }
+
+    #[test]
+    fn test_complex_type_def() {
+        let faces = Value::Complex(vec![face(1, "Ann", 0.1), face(2, "Bartholomew", 0.6)]);
+        let entries = vec![KeyValue::new(
+            Tag::STRM,
+            Value::Nested(vec![
+                KeyValue::new(Tag::STNM, Value::String("Faces".to_string())),
+                KeyValue::new(Tag::FACE, faces.clone()),
+                // described by the TYPE written for the first FACE
+                KeyValue::new(Tag::FACE, Value::Complex(vec![face(3, "Cy", 0.3)])),
+            ]),
+        )];
+        let written = write_gpmf(&entries).unwrap();
+        check_alignment(&written);
+
+        let parsed = parse_gpmf(&written).unwrap();
+        let Value::Nested(stream) = parsed[0].value() else {
+            panic!("Expected STRM, got {:?}", parsed[0]);
+        };
+        let keys: Vec<_> = stream.iter().map(|kv| kv.key().fourcc()).collect();
+        assert_eq!(keys, ["STNM", "TYPE", "FACE", "FACE"]);
+        let Value::Type(types) = stream[1].value() else {
+            panic!("Expected TYPE, got {:?}", stream[1]);
+        };
+        let letters: String = types.iter().map(|t| *t as u8 as char).collect();
+        assert_eq!(letters, format!("L{}ffff", "c".repeat("Bartholomew".len())));
+        let Value::Complex(samples) = stream[2].value() else {
+            panic!("Expected FACE, got {:?}", stream[2]);
+        };
+        assert!(matches!(&samples[1][1], Value::String(name) if name == "Bartholomew"));
+        assert!(matches!(samples[1][2], Value::F32(x) if x == 0.6));
+        assert!(matches!(&samples[0][1], Value::String(name) if name == "Ann"));
+
+        // a matching TYPE is kept as it is, so the parsed entries write the same bytes
+        assert_eq!(write_gpmf(&parsed).unwrap(), written);
+
+        // the same with the streaming writer
+        let mut writer = GpmfWriter::new(Vec::new());
+        stream_entries(&mut writer, &entries).unwrap();
+        assert_eq!(writer.finish().unwrap(), written);
+    }
+
+    #[test]
+    fn test_complex_type_def_errors() {
+        let mismatch = KeyValue::new(
+            Tag::FACE,
+            Value::Complex(vec![face(1, "Ann", 0.1), vec![Value::U32(2)]]),
+        );
+        assert!(matches!(
+            write_gpmf(&[mismatch]),
+            Err(WriteError::InconsistentSamples { sample: 1, .. })
+        ));
+
+        let adjacent = KeyValue::new(
+            Tag::FACE,
+            Value::Complex(vec![vec![
+                Value::String("a".to_string()),
+                Value::String("b".to_string()),
+            ]]),
+        );
+        assert!(matches!(
+            write_gpmf(&[adjacent]),
+            Err(WriteError::AdjacentStrings { .. })
+        ));
+
+        // a TYPE that does not describe the samples is replaced by one that does
+        let entries = [
+            KeyValue::new(Tag::TYPE, Value::Type(vec![Type::U16])),
+            KeyValue::new(Tag::FACE, Value::Complex(vec![face(1, "Ann", 0.1)])),
+        ];
+        let parsed = parse_gpmf(&write_gpmf(&entries).unwrap()).unwrap();
+        let keys: Vec<_> = parsed.iter().map(|kv| kv.key().fourcc()).collect();
+        assert_eq!(keys, ["TYPE", "TYPE", "FACE"]);
+        assert!(matches!(parsed[2].value(), Value::Complex(samples) if samples[0].len() == 6));
+    }
+
+    /// The number of samples of the entries with `tag` in a STRM
+    fn count_samples(stream: &KeyValue, tag: &Tag) -> usize {
+        let Value::Nested(children) = stream.value() else {
+            panic!("Expected STRM, got {:?}", stream);
+        };
+        children
+            .iter()
+            .filter(|kv| kv.key() == tag)
+            .map(|kv| match kv.value() {
+                Value::Simple(samples) => samples.len(),
+                value => panic!("Expected samples, got {:?}", value),
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_split_repeats() {
+        let samples: Vec<_> = (0..70_000)
+            .map(|i| vec![Value::I16(i as i16), Value::I16(1), Value::I16(-1)])
+            .collect();
+        let entries = vec![KeyValue::new(
+            Tag::STRM,
+            Value::Nested(vec![
+                KeyValue::new(Tag::STNM, Value::String("Accelerometer".to_string())),
+                KeyValue::new(Tag::ACCL, Value::Simple(samples)),
+            ]),
+        )];
+        assert!(matches!(
+            write_gpmf(&entries),
+            Err(WriteError::TooManySamples {
+                tag: Tag::ACCL,
+                repeat: 70_000
+            })
+        ));
+
+        let options = WriteOptions {
+            split_repeats: true,
+            ..Default::default()
+        };
+        let written = write_gpmf_opts(&entries, &options).unwrap();
+        check_alignment(&written);
+        let parsed = parse_gpmf(&written).unwrap();
+        assert_eq!(count_samples(&parsed[0], &Tag::ACCL), 70_000);
+        let Value::Nested(children) = parsed[0].value() else {
+            panic!("Expected STRM, got {:?}", parsed[0]);
+        };
+        let repeats: Vec<_> = children
+            .iter()
+            .filter_map(|kv| kv.header())
+            .map(|header| header.repeat())
+            .collect();
+        assert_eq!(repeats, [13, u16::MAX, 4465]);
+
+        let rewritten = write_gpmf(&parsed).unwrap();
+        assert_eq!(rewritten, written);
+        let reparsed = parse_gpmf(&rewritten).unwrap();
+        assert_eq!(count_samples(&reparsed[0], &Tag::ACCL), 70_000);
+
+        let mut writer = GpmfWriter::with_options(Vec::new(), options);
+        stream_entries(&mut writer, &entries).unwrap();
+        assert_eq!(writer.finish().unwrap(), written);
+    }
+
+    #[test]
+    fn test_raw_packing() {
+        // 300 samples of 255 bytes of an unknown type
+        let mut text = b"ZZZZz\xFF\x01\x2C".to_vec();
+        text.resize(8 + 255 * 300, 7);
+        let parsed = parse_gpmf(&text).unwrap();
+        assert!(matches!(
+            parsed[0].value(),
+            Value::Raw {
+                type_char: b'z',
+                ..
+            }
+        ));
+        assert_eq!(write_gpmf(&parsed).unwrap(), text);
+
+        // without a header the smallest structure size that fits is used
+        let raw = Value::Raw {
+            type_char: b'z',
+            bytes: vec![7; 255 * 300],
+        };
+        let written = write_gpmf(&[KeyValue::new(Tag::Other(*b"ZZZZ"), raw)]).unwrap();
+        assert_eq!(&written[4..8], b"z\x02\x95\x6A");
+        assert_eq!(parse_gpmf(&written).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_simple_strings() {
+        let name = KeyValue::new(
+            Tag::DVNM,
+            Value::Simple(vec![vec![Value::String("abcdef".to_string())]]),
+        );
+        let written = write_gpmf(&[name]).unwrap();
+        assert_eq!(&written[..8], b"DVNMc\x06\0\x01");
+        let reparsed = parse_gpmf(&written).unwrap();
+        assert_eq!(reparsed.len(), 1);
+        assert_eq!(reparsed[0].key(), &Tag::DVNM);
+
+        // every sample must be as wide as the first
+        let ragged = KeyValue::new(
+            Tag::DVNM,
+            Value::Simple(vec![
+                vec![Value::String("ab".to_string())],
+                vec![Value::String("abc".to_string())],
+            ]),
+        );
+        assert!(matches!(
+            write_gpmf(&[ragged]),
+            Err(WriteError::InconsistentSamples { sample: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_write_errors() {
+        let mixed = KeyValue::new(
+            Tag::ACCL,
+            Value::Simple(vec![vec![Value::I16(1)], vec![Value::U16(2)]]),
+        );
+        assert!(matches!(
+            write_gpmf(&[mixed]),
+            Err(WriteError::InconsistentSamples { sample: 1, .. })
+        ));
+
+        for c in ['é', '°', '€'] {
+            let char = KeyValue::new(Tag::RMRK, Value::Char(c));
+            assert!(
+                matches!(write_gpmf(&[char]), Err(WriteError::InvalidElement { .. })),
+                "{}",
+                c
+            );
+        }
+        let ascii = write_gpmf(&[KeyValue::new(Tag::RMRK, Value::Char('a'))]).unwrap();
+        assert!(matches!(parse_gpmf(&ascii).unwrap()[0].value(), Value::String(s) if s == "a"));
+
+        let wide = KeyValue::new(Tag::ACCL, Value::Simple(vec![vec![Value::F64(0.0); 32]]));
+        assert!(matches!(
+            write_gpmf(&[wide]),
+            Err(WriteError::SampleTooLarge { size: 256, .. })
+        ));
+    }
+
+    #[test]
+    fn test_gpsu_as_string() {
+        let text = [
+            b"STRM\0\x01\0\x18".as_slice(),
+            b"GPSUc\x01\0\x10230617142233.421",
+        ]
+        .concat();
+        let parsed = parse_gpmf(&text).unwrap();
+        let Value::Nested(stream) = parsed[0].value() else {
+            panic!("Expected STRM, got {:?}", parsed[0]);
+        };
+        assert!(matches!(stream[0].value(), Value::Simple(samples)
+            if matches!(samples[0][..], [Value::Date(_)])));
+        // the type letter is kept without WriteOptions::exact
+        assert_eq!(write_gpmf(&parsed).unwrap(), text);
+
+        // a date that is not read from a string is written as one
+        let date = KeyValue::new(Tag::GPSU, stream[0].value().clone());
+        assert_eq!(write_gpmf(&[date]).unwrap()[4], b'U');
+    }
+}
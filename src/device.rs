@@ -0,0 +1,108 @@
+//! Grouping of the parsed entries by the device that recorded them
+//!
+//! A payload holds one DEVC per device, e.g. the camera and a Karma drone or BLE sensor,
+//! each identified by its DVID and DVNM children.
+
+use crate::{KeyValue, Tag, Value};
+use tracing::debug;
+
+/// The entries of one device
+#[derive(Debug, Clone)]
+pub struct Device {
+    /// The device ID from DVID, 0 if there is none
+    pub id: u32,
+    /// The device name from DVNM, empty if there is none
+    pub name: String,
+    /// The children of the device's DEVC containers without DVID and DVNM, usually its STRMs
+    pub entries: Vec<KeyValue>,
+}
+
+/// Group the top level DEVC containers by device, in order of first appearance
+///
+/// DEVC containers with the same ID and name, e.g. from consecutive payloads, are merged into one device.
+/// Top level entries other than DEVC are dropped.
+pub fn group_devices(entries: Vec<KeyValue>) -> Vec<Device> {
+    let mut devices: Vec<Device> = Vec::new();
+    for KeyValue { key, value, .. } in entries {
+        let (Tag::DEVC, Value::Nested(children)) = (&key, value) else {
+            debug!("Dropping top level {} outside of a DEVC", key);
+            continue;
+        };
+
+        let mut id = 0;
+        let mut name = String::new();
+        let mut rest = Vec::with_capacity(children.len());
+        for child in children {
+            match (&child.key, &child.value) {
+                (Tag::DVID, value) => id = device_id(value).unwrap_or_default(),
+                (Tag::DVNM, Value::String(value)) => name = value.clone(),
+                _ => rest.push(child),
+            }
+        }
+
+        match devices.iter_mut().find(|d| d.id == id && d.name == name) {
+            Some(device) => device.entries.append(&mut rest),
+            None => devices.push(Device {
+                id,
+                name,
+                entries: rest,
+            }),
+        }
+    }
+    devices
+}
+
+/// The device ID of a DVID value, which is a 32 bit integer or a FourCC
+fn device_id(value: &Value) -> Option<u32> {
+    match value {
+        Value::Simple(samples) => match samples.first()?.first()? {
+            Value::U32(id) => Some(*id),
+            Value::I32(id) => Some(*id as u32),
+            Value::Tag(tag) => Some(u32::from_be_bytes(tag.fourcc_bytes())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::byteorder_gpmf::parse_gpmf;
+    use std::path::Path;
+
+    #[test]
+    fn test_group_devices() {
+        let text = std::fs::read(Path::new("samples").join("karma.raw")).unwrap();
+        let devices = group_devices(parse_gpmf(&text).unwrap());
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].id, 1);
+        assert_eq!(devices[0].name, "Camera");
+        assert_eq!(devices[1].id, 0x0100_E511);
+        assert_eq!(devices[1].name, "GoPro Karma v1.0");
+        for device in &devices {
+            assert!(!device.entries.is_empty());
+            assert!(device
+                .entries
+                .iter()
+                .all(|kv| !matches!(kv.key(), Tag::DVID | Tag::DVNM)));
+        }
+        assert!(devices[1].entries.iter().any(|kv| kv.key() == &Tag::STRM));
+    }
+
+    #[test]
+    fn test_merge_payloads() {
+        let text = std::fs::read(Path::new("samples").join("hero6+ble.raw")).unwrap();
+        let once = group_devices(parse_gpmf(&text).unwrap());
+        assert_eq!(once.len(), 2);
+        assert_eq!(once[1].name, "SENSORB6");
+
+        // two consecutive payloads
+        let twice = group_devices(parse_gpmf(&[text.clone(), text].concat()).unwrap());
+        assert_eq!(twice.len(), 2);
+        for (once, twice) in once.iter().zip(&twice) {
+            assert_eq!(once.id, twice.id);
+            assert_eq!(twice.entries.len(), 2 * once.entries.len());
+        }
+    }
+}
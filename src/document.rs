@@ -0,0 +1,199 @@
+//! Editing a GPMF stream in place
+//!
+//! A [`GpmfDocument`] keeps the bytes it was parsed from. Entries that are not changed are written back
+//! as they were read, only the changed entries and the containers holding them are encoded again.
+//!
+//! ```
+//! use gpmf::document::GpmfDocument;
+//! use gpmf::{Tag, Value};
+//!
+//! let text = std::fs::read("samples/karma.raw")?;
+//! let mut document = GpmfDocument::from_bytes(&text)?;
+//! for device in document.entries_mut() {
+//!     for kv in device.children_mut().into_iter().flatten() {
+//!         if kv.key() == &Tag::DVNM {
+//!             kv.set_value(Value::String("Renamed".to_string()));
+//!         }
+//!     }
+//! }
+//! let edited = document.to_bytes()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::byteorder_gpmf::write::write_spliced;
+use crate::byteorder_gpmf::{parse_gpmf, GpmfError, WriteError};
+use crate::KeyValue;
+
+/// A parsed GPMF stream together with the bytes it was parsed from
+///
+/// Change entries with [`KeyValue::set_value`] and [`KeyValue::children_mut`], which make them
+/// and their containers forget the bytes they were read from.
+#[derive(Debug, Clone)]
+pub struct GpmfDocument {
+    /// The parsed input
+    source: Vec<u8>,
+    /// The entries, changed or not
+    entries: Vec<KeyValue>,
+}
+
+impl GpmfDocument {
+    /// Parse the GPMF stream like [`parse_gpmf`], keeping a copy of the input
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`parse_gpmf`]
+    pub fn from_bytes(input: &[u8]) -> Result<Self, GpmfError> {
+        Ok(GpmfDocument {
+            entries: parse_gpmf(input)?,
+            source: input.to_vec(),
+        })
+    }
+
+    /// The top level entries
+    pub fn entries(&self) -> &[KeyValue] {
+        &self.entries
+    }
+
+    /// The top level entries for changing them, entries can also be added or removed
+    pub fn entries_mut(&mut self) -> &mut Vec<KeyValue> {
+        &mut self.entries
+    }
+
+    /// Write the entries, copying the unchanged ones from the parsed input
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`write_gpmf`](crate::byteorder_gpmf::write_gpmf) for the changed entries
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WriteError> {
+        let mut out = Vec::with_capacity(self.source.len());
+        write_spliced(&mut out, &self.entries, &self.source)?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Tag, Value};
+    use std::path::Path;
+
+    /// The children of the top level DEVC at `index` for changing them
+    fn device_mut(document: &mut GpmfDocument, index: usize) -> &mut Vec<KeyValue> {
+        document.entries_mut()[index].children_mut().unwrap()
+    }
+
+    #[test]
+    fn test_unchanged() {
+        for name in [
+            "hero5.raw",
+            "hero6.raw",
+            "hero6+ble.raw",
+            "Fusion.raw",
+            "karma.raw",
+        ] {
+            let text = std::fs::read(Path::new("samples").join(name)).unwrap();
+            let document = GpmfDocument::from_bytes(&text).unwrap();
+            assert_eq!(document.to_bytes().unwrap(), text, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_change_string() {
+        let text = std::fs::read(Path::new("samples").join("karma.raw")).unwrap();
+        let mut document = GpmfDocument::from_bytes(&text).unwrap();
+        // the second DEVC, of the drone
+        let devc = document.entries()[1].offset().unwrap() as usize;
+        let device = device_mut(&mut document, 1);
+        let dvnm = device.iter_mut().find(|kv| kv.key() == &Tag::DVNM).unwrap();
+        let (dvnm_offset, dvnm_len) = (dvnm.offset().unwrap() as usize, dvnm.raw_len().unwrap());
+        dvnm.set_value(Value::String("GoPro Karma v1.0 (serviced)".to_string()));
+
+        let edited = document.to_bytes().unwrap();
+        // everything before the DEVC header's length and its children before DVNM are unchanged
+        assert_eq!(edited[..devc + 4], text[..devc + 4]);
+        assert_eq!(edited[devc + 8..dvnm_offset], text[devc + 8..dvnm_offset]);
+        // the name is 12 bytes longer including padding, so is the DEVC
+        let grown = edited.len() - text.len();
+        assert_eq!(grown, 12);
+        let length = |bytes: &[u8]| u16::from_be_bytes([bytes[devc + 6], bytes[devc + 7]]);
+        assert_eq!(length(&edited), length(&text) + 12);
+        assert_eq!(
+            edited[dvnm_offset + dvnm_len + grown..],
+            text[dvnm_offset + dvnm_len..]
+        );
+
+        let reparsed = GpmfDocument::from_bytes(&edited).unwrap();
+        let Value::Nested(device) = reparsed.entries()[1].value() else {
+            panic!("Expected DEVC, got {:?}", reparsed.entries()[1]);
+        };
+        assert!(device.iter().any(|kv| matches!(kv.value(),
+            Value::String(name) if name == "GoPro Karma v1.0 (serviced)")));
+    }
+
+    #[test]
+    fn test_change_other_device() {
+        let text = std::fs::read(Path::new("samples").join("karma.raw")).unwrap();
+        let mut document = GpmfDocument::from_bytes(&text).unwrap();
+        let second = document.entries()[1].offset().unwrap() as usize;
+        let device = device_mut(&mut document, 0);
+        let dvnm = device.iter_mut().find(|kv| kv.key() == &Tag::DVNM).unwrap();
+        dvnm.set_value(Value::String("Kamera".to_string()));
+
+        let edited = document.to_bytes().unwrap();
+        assert_eq!(edited.len(), text.len());
+        assert_eq!(edited[second..], text[second..]);
+        assert_ne!(edited[..second], text[..second]);
+    }
+
+    #[cfg(feature = "dates")]
+    #[test]
+    fn test_shift_gpsu() {
+        let text = std::fs::read(Path::new("samples").join("hero5.raw")).unwrap();
+        let mut document = GpmfDocument::from_bytes(&text).unwrap();
+        let shift = chrono::Duration::hours(1);
+        let mut shifted = Vec::new();
+        for device in document.entries_mut() {
+            for stream in device.children_mut().into_iter().flatten() {
+                let Some(children) = stream.children_mut() else {
+                    continue;
+                };
+                for kv in children.iter_mut().filter(|kv| kv.key() == &Tag::GPSU) {
+                    if let Value::Simple(samples) = kv.value() {
+                        if let [Value::Date(date)] = samples[0][..] {
+                            shifted.push(date + shift);
+                            kv.set_value(Value::Simple(vec![vec![Value::Date(date + shift)]]));
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(shifted.len(), 1);
+
+        let edited = document.to_bytes().unwrap();
+        // a date is always 16 bytes, so nothing moves
+        assert_eq!(edited.len(), text.len());
+        let differing: Vec<_> = (0..text.len()).filter(|i| edited[*i] != text[*i]).collect();
+        assert!(
+            differing.iter().all(|i| (3936..3952).contains(i)),
+            "{:?}",
+            differing
+        );
+
+        let reparsed = GpmfDocument::from_bytes(&edited).unwrap();
+        let Value::Nested(device) = reparsed.entries()[0].value() else {
+            panic!("Expected DEVC");
+        };
+        let Value::Nested(stream) = device
+            .iter()
+            .find(|kv| kv.offset() == Some(3884))
+            .unwrap()
+            .value()
+        else {
+            panic!("Expected STRM");
+        };
+        let gpsu = stream.iter().find(|kv| kv.key() == &Tag::GPSU).unwrap();
+        assert!(
+            matches!(gpsu.value(), Value::Simple(s) if matches!(s[0][..], [Value::Date(date)] if date == shifted[0]))
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Typed enumerations for the streams that encode a small set of states as integers
+//!
+//! Undocumented values are kept as `Unknown` instead of being rejected,
+//! so converting from the raw integer never fails.
+
+use crate::{KeyValue, Tag, Value};
+use std::fmt;
+
+/// GPS fix from the GPSF stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpsFix {
+    /// 0 - no lock
+    NoLock,
+    /// 2 - 2D lock
+    Lock2D,
+    /// 3 - 3D lock
+    Lock3D,
+    /// Any other value
+    Unknown(u32),
+}
+
+impl GpsFix {
+    /// The name of the state
+    pub fn name(&self) -> &'static str {
+        match self {
+            GpsFix::NoLock => "NoLock",
+            GpsFix::Lock2D => "Lock2D",
+            GpsFix::Lock3D => "Lock3D",
+            GpsFix::Unknown(_) => "Unknown",
+        }
+    }
+
+    /// The numeric value as stored in the GPMF stream
+    pub fn value(&self) -> u32 {
+        match self {
+            GpsFix::NoLock => 0,
+            GpsFix::Lock2D => 2,
+            GpsFix::Lock3D => 3,
+            GpsFix::Unknown(value) => *value,
+        }
+    }
+}
+
+impl From<u32> for GpsFix {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => GpsFix::NoLock,
+            2 => GpsFix::Lock2D,
+            3 => GpsFix::Lock3D,
+            value => GpsFix::Unknown(value),
+        }
+    }
+}
+
+/// Wind processing state from the first field of the WNDM stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindProcessing {
+    /// 0 - wind processing is not active
+    Off,
+    /// 1 - wind processing is active
+    On,
+    /// Any other value
+    Unknown(u32),
+}
+
+impl WindProcessing {
+    /// The name of the state
+    pub fn name(&self) -> &'static str {
+        match self {
+            WindProcessing::Off => "Off",
+            WindProcessing::On => "On",
+            WindProcessing::Unknown(_) => "Unknown",
+        }
+    }
+
+    /// The numeric value as stored in the GPMF stream
+    pub fn value(&self) -> u32 {
+        match self {
+            WindProcessing::Off => 0,
+            WindProcessing::On => 1,
+            WindProcessing::Unknown(value) => *value,
+        }
+    }
+}
+
+impl From<u32> for WindProcessing {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => WindProcessing::Off,
+            1 => WindProcessing::On,
+            value => WindProcessing::Unknown(value),
+        }
+    }
+}
+
+/// Wet microphone state from the first field of the MWET stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MicWet {
+    /// 0 - the microphones are dry
+    Dry,
+    /// 1 - some of the microphones are wet
+    Wet,
+    /// Any other value
+    Unknown(u32),
+}
+
+impl MicWet {
+    /// The name of the state
+    pub fn name(&self) -> &'static str {
+        match self {
+            MicWet::Dry => "Dry",
+            MicWet::Wet => "Wet",
+            MicWet::Unknown(_) => "Unknown",
+        }
+    }
+
+    /// The numeric value as stored in the GPMF stream
+    pub fn value(&self) -> u32 {
+        match self {
+            MicWet::Dry => 0,
+            MicWet::Wet => 1,
+            MicWet::Unknown(value) => *value,
+        }
+    }
+}
+
+impl From<u32> for MicWet {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => MicWet::Dry,
+            1 => MicWet::Wet,
+            value => MicWet::Unknown(value),
+        }
+    }
+}
+
+/// A flag value of one of the enumerated streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagValue {
+    /// GPSF
+    GpsFix(GpsFix),
+    /// WNDM
+    WindProcessing(WindProcessing),
+    /// MWET
+    MicWet(MicWet),
+}
+
+impl FlagValue {
+    /// The flag for a raw value of the given tag, or `None` if the tag is not an enumerated stream
+    pub fn new(tag: &Tag, value: u32) -> Option<FlagValue> {
+        match tag {
+            Tag::GPSF => Some(FlagValue::GpsFix(value.into())),
+            Tag::WNDM => Some(FlagValue::WindProcessing(value.into())),
+            Tag::MWET => Some(FlagValue::MicWet(value.into())),
+            _ => None,
+        }
+    }
+
+    /// The flags of every sample of an entry, taken from the first field of each sample.
+    ///
+    /// Returns `None` if the tag is not an enumerated stream or the values are not unsigned integers
+    pub fn from_key_value(key_value: &KeyValue) -> Option<Vec<FlagValue>> {
+        let Value::Simple(samples) = key_value.value() else {
+            return None;
+        };
+        samples
+            .iter()
+            .map(|sample| {
+                let value = match sample.first()? {
+                    Value::U8(v) => *v as u32,
+                    Value::U16(v) => *v as u32,
+                    Value::U32(v) => *v,
+                    _ => return None,
+                };
+                FlagValue::new(key_value.key(), value)
+            })
+            .collect()
+    }
+
+    /// The name of the state
+    pub fn name(&self) -> &'static str {
+        match self {
+            FlagValue::GpsFix(flag) => flag.name(),
+            FlagValue::WindProcessing(flag) => flag.name(),
+            FlagValue::MicWet(flag) => flag.name(),
+        }
+    }
+
+    /// The numeric value as stored in the GPMF stream
+    pub fn value(&self) -> u32 {
+        match self {
+            FlagValue::GpsFix(flag) => flag.value(),
+            FlagValue::WindProcessing(flag) => flag.value(),
+            FlagValue::MicWet(flag) => flag.value(),
+        }
+    }
+}
+
+impl fmt::Display for FlagValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlagValue::GpsFix(GpsFix::Unknown(v))
+            | FlagValue::WindProcessing(WindProcessing::Unknown(v))
+            | FlagValue::MicWet(MicWet::Unknown(v)) => write!(f, "Unknown({})", v),
+            flag => f.write_str(flag.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::byteorder_gpmf::parse_gpmf;
+    use std::path::Path;
+
+    #[test]
+    fn test_gps_fix() {
+        assert_eq!(GpsFix::from(0), GpsFix::NoLock);
+        assert_eq!(GpsFix::from(2), GpsFix::Lock2D);
+        assert_eq!(GpsFix::from(3), GpsFix::Lock3D);
+        assert_eq!(GpsFix::from(1), GpsFix::Unknown(1));
+        [0, 1, 2, 3, 42]
+            .iter()
+            .for_each(|v| assert_eq!(GpsFix::from(*v).value(), *v));
+    }
+
+    #[test]
+    fn test_wind_processing() {
+        assert_eq!(WindProcessing::from(0), WindProcessing::Off);
+        assert_eq!(WindProcessing::from(1), WindProcessing::On);
+        assert_eq!(WindProcessing::from(2), WindProcessing::Unknown(2));
+        [0, 1, 2]
+            .iter()
+            .for_each(|v| assert_eq!(WindProcessing::from(*v).value(), *v));
+    }
+
+    #[test]
+    fn test_mic_wet() {
+        assert_eq!(MicWet::from(0), MicWet::Dry);
+        assert_eq!(MicWet::from(1), MicWet::Wet);
+        assert_eq!(MicWet::from(255), MicWet::Unknown(255));
+        [0, 1, 255]
+            .iter()
+            .for_each(|v| assert_eq!(MicWet::from(*v).value(), *v));
+    }
+
+    #[test]
+    fn test_flag_value() {
+        assert_eq!(
+            FlagValue::new(&Tag::GPSF, 3),
+            Some(FlagValue::GpsFix(GpsFix::Lock3D))
+        );
+        assert_eq!(FlagValue::new(&Tag::ACCL, 3), None);
+        assert_eq!(FlagValue::new(&Tag::GPSF, 3).unwrap().to_string(), "Lock3D");
+        assert_eq!(
+            FlagValue::new(&Tag::MWET, 9).unwrap().to_string(),
+            "Unknown(9)"
+        );
+    }
+
+    /// Find the first entry with the tag, searching nested entries depth first
+    fn find<'a>(entries: &'a [KeyValue], tag: &Tag) -> Option<&'a KeyValue> {
+        entries.iter().find_map(|kv| match kv.value() {
+            _ if kv.key() == tag => Some(kv),
+            Value::Nested(nested) => find(nested, tag),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_gpsf_sample() {
+        let text = std::fs::read(Path::new("samples").join("hero5.raw")).unwrap();
+        let res = parse_gpmf(&text).unwrap();
+        let gpsf = find(&res, &Tag::GPSF).unwrap();
+        let flags = FlagValue::from_key_value(gpsf).unwrap();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0], FlagValue::GpsFix(GpsFix::Lock3D));
+    }
+}
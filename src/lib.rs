@@ -31,9 +31,16 @@
 //! * [ ] Return data in chronological order using Iterator and Tournament Tree
 //! * [ ] Extract metadata from Live Stream via WiFi and Rtmp Url in realtime
 //! * [ ] Handle exif data in images
-//! * [ ] Writer
+//! * [x] Writer
 //! * [ ] Roundtrip sensor data
 //!
+//! # Features
+//!
+//! * `dates` (default): **U** dates are parsed into `chrono` values, otherwise the raw 16 bytes are kept
+//! * `fixedpoint` (default): **q** and **Q** numbers are parsed into `fixed` values, otherwise the raw integers are kept
+//!
+//! Disabling both gives a parser without the `chrono` and `fixed` dependency trees, for tiny builds.
+//!
 //! # Example
 //!
 //! ```
@@ -78,18 +85,32 @@
     clippy::missing_errors_doc,
     clippy::missing_panics_doc
 )]
-#![feature(cursor_remaining)]
 #![feature(buf_read_has_data_left)]
 
+pub mod builder;
 pub mod byteorder_gpmf;
+pub mod device;
+pub mod document;
+pub mod flags;
+pub mod strip;
 
+#[cfg(feature = "dates")]
 use chrono::{DateTime, Utc};
+#[cfg(feature = "fixedpoint")]
 use fixed::types::{I16F16, I32F32};
 
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
 use num_enum::TryFromPrimitive;
-use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
-use tracing::{debug, enabled, error, info, span, trace, warn, Level};
+use strum::{Display, EnumIter, EnumString, IntoEnumIterator, IntoStaticStr};
+use tracing::warn;
 
+/// The format of the **U** UTC Date and Time string
+#[cfg(feature = "dates")]
 const DATE_FORMAT: &str = "%y%m%d%H%M%S%.3f";
 
 /// Entry
@@ -100,16 +121,203 @@ pub enum Entry {
     Seq(Vec<KeyValue>),
 }
 
-/// Key Value struct (not used at present)
+/// A KLV entry: its FourCC key and value, and for parsed entries where and how it was read
+///
+/// The header, raw bytes and span of the input are kept so that unchanged entries can be written back
+/// as they were read, see [`byteorder_gpmf::WriteOptions::exact`] and [`document::GpmfDocument`].
+/// Entries built with [`KeyValue::new`] or changed with [`KeyValue::set_value`] have none of them.
 #[derive(Debug, Clone)]
 pub struct KeyValue {
+    /// The FourCC key
     key: Tag,
+    /// The parsed value
     value: Value,
+    /// The header the entry was read with
+    header: Option<Header>,
+    /// The data bytes of a [`Value::String`], [`Value::Strings`] or [`Value::Type`] as they were read, including NUL padding.
+    /// With [`byteorder_gpmf::ParseOptions::exact`] the data bytes of every entry but nested containers
+    raw: Option<Vec<u8>>,
+    /// The offset of the entry in the input and its length in bytes
+    span: Option<(u64, usize)>,
+    /// The padding bytes after the data as they were read, kept with [`byteorder_gpmf::ParseOptions::exact`]
+    padding: Option<Vec<u8>>,
+}
+
+/// The type, size and repeat fields of a KLV entry as they were read from the stream
+///
+/// Keeping them allows the original packing to be reproduced, e.g. a string padded with NULs inside
+/// its declared size or samples packed as one repeat of a larger structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// The raw type byte
+    type_u8: u8,
+    /// The size of one sample in bytes
+    size: u8,
+    /// The number of samples
+    repeat: u16,
+}
+
+impl Header {
+    /// The raw type byte
+    pub fn type_u8(&self) -> u8 {
+        self.type_u8
+    }
+
+    /// The size of one sample in bytes
+    pub fn size(&self) -> u8 {
+        self.size
+    }
+
+    /// The number of samples
+    pub fn repeat(&self) -> u16 {
+        self.repeat
+    }
+
+    /// The number of data bytes following the header, excluding padding
+    pub fn data_len(&self) -> usize {
+        // at most 255 * 65535, which can not overflow even a 32 bit usize
+        self.size as usize * self.repeat as usize
+    }
+}
+
+impl KeyValue {
+    /// An entry that was not read from a GPMF stream, e.g. to write with [`byteorder_gpmf::write_gpmf`]
+    pub fn new(key: Tag, value: Value) -> Self {
+        KeyValue {
+            key,
+            value,
+            header: None,
+            raw: None,
+            span: None,
+            padding: None,
+        }
+    }
+
+    /// The FourCC key of the entry
+    pub fn key(&self) -> &Tag {
+        &self.key
+    }
+
+    /// The value of the entry
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    /// The original header, `None` if the entry was not read from a GPMF stream or was changed since
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    /// Replace the value of the entry
+    ///
+    /// The entry then no longer matches the bytes it was read from, so like an entry built with
+    /// [`KeyValue::new`] it loses its header, offset and kept bytes and is written from its value.
+    pub fn set_value(&mut self, value: Value) {
+        self.value = value;
+        self.raw = None;
+        self.forget_input();
+    }
+
+    /// The children of a nested container for changing them, `None` for other values
+    ///
+    /// Like [`KeyValue::set_value`] the container is then written from its children,
+    /// the children that are not changed keep what was read.
+    pub fn children_mut(&mut self) -> Option<&mut Vec<KeyValue>> {
+        if !matches!(self.value, Value::Nested(_)) {
+            return None;
+        }
+        self.forget_input();
+        match &mut self.value {
+            Value::Nested(children) => Some(children),
+            _ => None,
+        }
+    }
+
+    /// Forget the header, offset and padding the entry was read with
+    fn forget_input(&mut self) {
+        self.header = None;
+        self.span = None;
+        self.padding = None;
+    }
+
+    /// The offset of the entry in the parsed input, also for entries of nested containers.
+    /// `None` if the entry was not read from a GPMF stream or was changed since
+    pub fn offset(&self) -> Option<u64> {
+        self.span.map(|(offset, _)| offset)
+    }
+
+    /// The number of bytes of the entry in the parsed input including the header and padding,
+    /// so the input from [`KeyValue::offset`] on this length parses to the same entry.
+    /// `None` if the entry was not read from a GPMF stream or was changed since
+    pub fn raw_len(&self) -> Option<usize> {
+        self.span.map(|(_, raw_len)| raw_len)
+    }
+
+    /// The fixed width fields of a [`Value::String`] or [`Value::Strings`] as they were read
+    ///
+    /// Unlike the decoded strings they keep the NUL padding and any bytes after the first NUL,
+    /// one field per string. `None` for other values and entries not read from a GPMF stream.
+    pub fn raw_strings(&self) -> Option<impl Iterator<Item = &[u8]>> {
+        let raw = self.raw.as_deref()?;
+        let width = match (&self.value, &self.header) {
+            (Value::Strings(_), Some(header)) => header.size as usize,
+            (Value::String(_) | Value::Strings(_), _) => raw.len(),
+            _ => return None,
+        };
+        Some(raw.chunks(width.max(1)))
+    }
+
+    /// The number of elements in each sample, e.g. 3 for a three axis ACCL, 5 for GPS5 and 1 for SHUT
+    ///
+    /// It is derived from the original header, so the header size is this times the size of the type.
+    /// `None` for nested containers, complex entries, entries kept as [`Value::Raw`]
+    /// and entries not read from a GPMF stream.
+    pub fn elements_per_sample(&self) -> Option<usize> {
+        let header = self.header.as_ref()?;
+        match Type::try_from(header.type_u8).ok()? {
+            Type::Nested | Type::Complex => None,
+            typ => (header.size as usize)
+                .is_multiple_of(typ.size())
+                .then(|| header.size as usize / typ.size()),
+        }
+    }
 }
 
 /// The data type of the sensor data
+///
+/// New types may be added in future versions, so matches outside this crate need a wildcard arm,
+/// which keeps them compiling when one is added:
+///
+/// ```
+/// use gpmf::Type;
+///
+/// fn is_integer(t: Type) -> bool {
+///     match t {
+///         Type::I8 | Type::U8 | Type::I16 | Type::U16 => true,
+///         Type::I32 | Type::U32 | Type::I64 | Type::U64 => true,
+///         _ => false,
+///     }
+/// }
+/// assert!(is_integer(Type::U16));
+/// ```
+///
+/// Without one, even a match naming every current type does not compile:
+///
+/// ```compile_fail,E0004
+/// use gpmf::Type;
+///
+/// fn is_integer(t: Type) -> bool {
+///     match t {
+///         Type::I8 | Type::U8 | Type::I16 | Type::U16 => true,
+///         Type::I32 | Type::U32 | Type::I64 | Type::U64 => true,
+///         Type::Char | Type::F64 | Type::F32 | Type::FourCC | Type::U128 => false,
+///         Type::Fixed32 | Type::Fixed64 | Type::Date | Type::Complex | Type::Nested => false,
+///     }
+/// }
+/// ```
 #[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, EnumString, Display, TryFromPrimitive)]
 #[repr(u8)]
+#[non_exhaustive]
 pub enum Type {
     /// | **b** | single byte signed integer | int8\_t | -128 to 127 |
     I8 = b'b',
@@ -202,7 +410,42 @@ pub enum Model {
 }
 
 /// The value of the data,
+///
+/// Use [`Value::kind`] to inspect a value without matching on every variant.
+/// New variants may be added in future versions, so matches outside this crate need a wildcard arm:
+///
+/// ```
+/// use gpmf::Value;
+///
+/// fn is_text(value: &Value) -> bool {
+///     match value {
+///         Value::String(_) | Value::Strings(_) => true,
+///         _ => false,
+///     }
+/// }
+/// assert!(is_text(&Value::String("GPS".to_string())));
+/// ```
+///
+/// Without one, even a match naming every current variant does not compile:
+///
+/// ```compile_fail,E0004
+/// use gpmf::Value;
+///
+/// fn is_text(value: &Value) -> bool {
+///     match value {
+///         Value::String(_) | Value::Strings(_) => true,
+///         // every other variant, hidden here
+/// #         Value::I8(_) | Value::U8(_) | Value::Char(_) | Value::F64(_)
+/// #         | Value::F32(_) | Value::Tag(_) | Value::U128(_) | Value::I64(_)
+/// #         | Value::U64(_) | Value::I32(_) | Value::U32(_) | Value::Fixed32(_)
+/// #         | Value::Fixed64(_) | Value::I16(_) | Value::U16(_) | Value::Date(_)
+/// #         | Value::RawDate(_) | Value::NoDate(_) | Value::Complex(_) | Value::Nested(_)
+/// #         | Value::Simple(_) | Value::Type(_) | Value::Empty(_) | Value::Raw { .. } => false,
+///     }
+/// }
+/// ```
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum Value {
     ///| **b** | single byte signed integer | int8\_t | -128 to 127 |
     I8(i8),
@@ -211,6 +454,8 @@ pub enum Value {
     /// | **c** | single byte &#39;c&#39; style ASCII character string | char | Optionally NULL terminated - size/repeat sets the length |
     Char(char),
     /// | **c** | single byte &#39;c&#39; style ASCII character string | char | Optionally NULL terminated - size/repeat sets the length |
+    ///
+    /// Decoded as UTF-8, invalid bytes such as Latin-1 characters are replaced with U+FFFD
     String(String),
     /// | **d** | 64-bit double precision (IEEE 754) | double |   |
     F64(f64),
@@ -229,61 +474,266 @@ pub enum Value {
     /// | **L** | 32-bit unsigned integer | uint32\_t |   |
     U32(u32),
     /// | **q** | 32-bit Q Number Q15.16 | uint32\_t | 16-bit integer (A) with 16-bit fixed point (B) for A.B value (range -32768.0 to 32767.99998) |
+    #[cfg(feature = "fixedpoint")]
     Fixed32(I16F16),
+    /// | **q** | 32-bit Q Number Q15.16 | uint32\_t | Raw bits, without the `fixedpoint` feature |
+    #[cfg(not(feature = "fixedpoint"))]
+    Fixed32(i32),
     /// | **Q** | 64-bit Q Number Q31.32 | uint64\_t | 32-bit integer (A) with 32-bit fixed point (B) for A.B value. |
+    #[cfg(feature = "fixedpoint")]
     Fixed64(I32F32),
+    /// | **Q** | 64-bit Q Number Q31.32 | uint64\_t | Raw bits, without the `fixedpoint` feature |
+    #[cfg(not(feature = "fixedpoint"))]
+    Fixed64(i64),
     /// | **s** | 16-bit signed integer | int16\_t | -32768 to 32768 |
     I16(i16),
     /// | **S** | 16-bit unsigned integer | uint16\_t | 0 to 65536 |
     U16(u16),
     /// | **U** | UTC Date and Time string | char utcdate\[16\] | Date + UTC Time format yymmddhhmmss.sss - (years 20xx covered) |
+    #[cfg(feature = "dates")]
     Date(DateTime<Utc>),
+    /// | **U** | UTC Date and Time string | char utcdate\[16\] | Raw bytes, without the `dates` feature |
+    #[cfg(not(feature = "dates"))]
+    Date([u8; 16]),
+    /// | **U** | UTC Date and Time string | char utcdate\[16\] | Raw bytes of a date that could not be parsed, e.g. before GPS lock |
+    RawDate([u8; 16]),
+    /// | **U** | UTC Date and Time string | char utcdate\[16\] | Placeholder of zeros or spaces written before GPS lock, with its raw bytes |
+    NoDate([u8; 16]),
 
     /// | **?** | data structure is complex | TYPE | Structure is defined with a preceding TYPE |
+    ///
+    /// Each sample holds one value per field, a run of characters like `c[4]` is a single [`Value::String`]
     Complex(Vec<Vec<Value>>),
     /// | **null** | Nested metadata | uint32\_t | The data within is GPMF structured KLV data |
     Nested(Vec<KeyValue>),
     /// Simple
     Simple(Vec<Vec<Value>>),
     /// Type
+    ///
+    /// A TYPE defines the layout of the [`Value::Complex`] entries after it in the same container,
+    /// until the next TYPE replaces it.
     Type(Vec<Type>),
-    /// Strings
+    /// Strings, decoded like [`Value::String`]
     Strings(Vec<String>),
+    /// An entry with no samples, i.e. a repeat or structure size of zero, with its declared type
+    Empty(Type),
+    /// Data of a type letter this crate does not know, kept as is
+    Raw {
+        /// The type letter from the header
+        type_char: u8,
+        /// The data bytes, without padding
+        bytes: Vec<u8>,
+    },
 }
 
 impl Value {
-    /// The datatype of the value
-    pub fn datatype(&self) -> Type {
+    /// The datatype of the value, as in the type letter of the header it is written with
+    ///
+    /// [`Value::Simple`] has the datatype of its elements, [`Value::Type`] and [`Value::Strings`] are
+    /// written as characters. Returns `None` for a [`Value::Raw`] with a letter this crate does not know
+    /// and for a [`Value::Simple`] without elements.
+    pub fn datatype(&self) -> Option<Type> {
         match self {
-            Value::I8(_) => Type::I8,
-            Value::U8(_) => Type::U8,
-            Value::Char(_) => Type::Char,
-            Value::String(_) => Type::Char,
-            Value::F64(_) => Type::F64,
-            Value::F32(_) => Type::F32,
-            Value::Tag(_) => Type::FourCC,
-            Value::U128(_) => Type::U128,
-            Value::I64(_) => Type::I64,
-            Value::U64(_) => Type::U64,
-            Value::I32(_) => Type::I32,
-            Value::U32(_) => Type::U32,
-            Value::Fixed32(_) => Type::Fixed32,
-            Value::Fixed64(_) => Type::Fixed64,
-            Value::I16(_) => Type::I16,
-            Value::U16(_) => Type::U16,
-            Value::Date(_) => Type::Date,
-            Value::Complex(_) => Type::Complex,
-            Value::Nested(_) => Type::Nested,
-            _ => unimplemented!(),
+            Value::I8(_) => Some(Type::I8),
+            Value::U8(_) => Some(Type::U8),
+            Value::Char(_) => Some(Type::Char),
+            Value::String(_) => Some(Type::Char),
+            Value::F64(_) => Some(Type::F64),
+            Value::F32(_) => Some(Type::F32),
+            Value::Tag(_) => Some(Type::FourCC),
+            Value::U128(_) => Some(Type::U128),
+            Value::I64(_) => Some(Type::I64),
+            Value::U64(_) => Some(Type::U64),
+            Value::I32(_) => Some(Type::I32),
+            Value::U32(_) => Some(Type::U32),
+            Value::Fixed32(_) => Some(Type::Fixed32),
+            Value::Fixed64(_) => Some(Type::Fixed64),
+            Value::I16(_) => Some(Type::I16),
+            Value::U16(_) => Some(Type::U16),
+            Value::Date(_) => Some(Type::Date),
+            Value::RawDate(_) => Some(Type::Date),
+            Value::NoDate(_) => Some(Type::Date),
+            Value::Complex(_) => Some(Type::Complex),
+            Value::Nested(_) => Some(Type::Nested),
+            Value::Simple(samples) => samples.iter().flatten().next().and_then(Value::datatype),
+            Value::Type(_) => Some(Type::Char),
+            Value::Strings(_) => Some(Type::Char),
+            Value::Empty(t) => Some(*t),
+            Value::Raw { type_char, .. } => Type::try_from(*type_char).ok(),
+        }
+    }
+
+    /// The kind of the value, e.g. to tell a [`Value::Simple`] from the elements in it
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Value::I8(_) => ValueKind::I8,
+            Value::U8(_) => ValueKind::U8,
+            Value::Char(_) => ValueKind::Char,
+            Value::String(_) => ValueKind::String,
+            Value::F64(_) => ValueKind::F64,
+            Value::F32(_) => ValueKind::F32,
+            Value::Tag(_) => ValueKind::Tag,
+            Value::U128(_) => ValueKind::U128,
+            Value::I64(_) => ValueKind::I64,
+            Value::U64(_) => ValueKind::U64,
+            Value::I32(_) => ValueKind::I32,
+            Value::U32(_) => ValueKind::U32,
+            Value::Fixed32(_) => ValueKind::Fixed32,
+            Value::Fixed64(_) => ValueKind::Fixed64,
+            Value::I16(_) => ValueKind::I16,
+            Value::U16(_) => ValueKind::U16,
+            Value::Date(_) => ValueKind::Date,
+            Value::RawDate(_) => ValueKind::RawDate,
+            Value::NoDate(_) => ValueKind::NoDate,
+            Value::Complex(_) => ValueKind::Complex,
+            Value::Nested(_) => ValueKind::Nested,
+            Value::Simple(_) => ValueKind::Simple,
+            Value::Type(_) => ValueKind::Type,
+            Value::Strings(_) => ValueKind::Strings,
+            Value::Empty(_) => ValueKind::Empty,
+            Value::Raw { .. } => ValueKind::Raw,
         }
     }
 }
 
+/// The kind of a [`Value`], one per variant without the data
+///
+/// ```
+/// use gpmf::ValueKind;
+///
+/// fn describe(kind: ValueKind) -> &'static str {
+///     match kind {
+///         ValueKind::Nested => "container",
+///         ValueKind::String | ValueKind::Strings => "text",
+///         _ => "data",
+///     }
+/// }
+/// assert_eq!(describe(ValueKind::Nested), "container");
+/// ```
+///
+/// Like [`Value`] more kinds may be added, so a match without a wildcard arm does not compile:
+///
+/// ```compile_fail,E0004
+/// use gpmf::ValueKind;
+///
+/// fn describe(kind: ValueKind) -> &'static str {
+///     match kind {
+///         ValueKind::Nested => "container",
+///         ValueKind::String | ValueKind::Strings => "text",
+///         // every other kind, hidden here
+/// #         ValueKind::I8 | ValueKind::U8 | ValueKind::Char | ValueKind::F64 | ValueKind::F32
+/// #         | ValueKind::Tag | ValueKind::U128 | ValueKind::I64 | ValueKind::U64 | ValueKind::I32
+/// #         | ValueKind::U32 | ValueKind::Fixed32 | ValueKind::Fixed64 | ValueKind::I16 | ValueKind::U16
+/// #         | ValueKind::Date | ValueKind::RawDate | ValueKind::NoDate | ValueKind::Complex | ValueKind::Simple
+/// #         | ValueKind::Type | ValueKind::Empty | ValueKind::Raw => "data",
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, Display)]
+#[non_exhaustive]
+pub enum ValueKind {
+    /// See [`Value::I8`]
+    I8,
+    /// See [`Value::U8`]
+    U8,
+    /// See [`Value::Char`]
+    Char,
+    /// See [`Value::String`]
+    String,
+    /// See [`Value::F64`]
+    F64,
+    /// See [`Value::F32`]
+    F32,
+    /// See [`Value::Tag`]
+    Tag,
+    /// See [`Value::U128`]
+    U128,
+    /// See [`Value::I64`]
+    I64,
+    /// See [`Value::U64`]
+    U64,
+    /// See [`Value::I32`]
+    I32,
+    /// See [`Value::U32`]
+    U32,
+    /// See [`Value::Fixed32`]
+    Fixed32,
+    /// See [`Value::Fixed64`]
+    Fixed64,
+    /// See [`Value::I16`]
+    I16,
+    /// See [`Value::U16`]
+    U16,
+    /// See [`Value::Date`]
+    Date,
+    /// See [`Value::RawDate`]
+    RawDate,
+    /// See [`Value::NoDate`]
+    NoDate,
+    /// See [`Value::Complex`]
+    Complex,
+    /// See [`Value::Nested`]
+    Nested,
+    /// See [`Value::Simple`]
+    Simple,
+    /// See [`Value::Type`]
+    Type,
+    /// See [`Value::Strings`]
+    Strings,
+    /// See [`Value::Empty`]
+    Empty,
+    /// See [`Value::Raw`]
+    Raw,
+}
+
 /// The FourCC key of the data
 ///
 /// There are some undocumented tags present in GPMF data.
 /// Currently warnings are logged for unsupported tags.
-#[derive(Debug, Clone, PartialEq, EnumString, EnumIter, Display)]
+///
+/// Parsing from a string only accepts exactly four bytes, anything else is not a FourCC.
+/// Unsupported tags are kept as [`Tag::Other`] and displayed with non printable characters escaped as `\xNN`.
+///
+/// Tags are added as they are documented, so matches outside this crate need a wildcard arm:
+///
+/// ```
+/// use gpmf::Tag;
+///
+/// fn is_container(tag: &Tag) -> bool {
+///     match tag {
+///         Tag::DEVC | Tag::STRM => true,
+///         _ => false,
+///     }
+/// }
+/// assert!(is_container(&Tag::STRM));
+/// ```
+///
+/// Without one, even a match naming every current tag does not compile:
+///
+/// ```compile_fail,E0004
+/// use gpmf::Tag;
+///
+/// fn is_container(tag: &Tag) -> bool {
+///     match tag {
+///         Tag::DEVC | Tag::STRM => true,
+///         // every other tag, hidden here
+/// #         Tag::DVID | Tag::DVNM | Tag::STNM | Tag::RMRK
+/// #         | Tag::SCAL | Tag::SIUN | Tag::UNIT | Tag::TYPE
+/// #         | Tag::TSMP | Tag::TIMO | Tag::EMPT | Tag::TICK
+/// #         | Tag::TOCK | Tag::TMPC | Tag::ACCL | Tag::GYRO
+/// #         | Tag::ISOG | Tag::SHUT | Tag::GPS5 | Tag::GPSU
+/// #         | Tag::GPSF | Tag::GPSP | Tag::MAGN | Tag::STMP
+/// #         | Tag::FACE | Tag::FCNM | Tag::ISOE | Tag::ALLD
+/// #         | Tag::WBAL | Tag::WRGB | Tag::YAVG | Tag::HUES
+/// #         | Tag::UNIF | Tag::SCEN | Tag::SROT | Tag::CORI
+/// #         | Tag::IORI | Tag::GRAV | Tag::WNDM | Tag::MWET
+/// #         | Tag::AALP | Tag::DISP | Tag::MSKP | Tag::LSKP
+/// #         | Tag::GPS9 | Tag::HMMT | Tag::KBAT | Tag::Other(_) => false,
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, EnumIter, IntoStaticStr)]
+#[non_exhaustive]
 pub enum Tag {
     ///unique device source for metadata
     /// Each connected device starts with DEVC. A GoPro camera or Karma drone would have their own DEVC for nested metadata to follow. |
@@ -532,9 +982,111 @@ pub enum Tag {
     #[strum(serialize = "KBAT", to_string = "KBAT UNDOCUMENTED Battery Status ???")]
     KBAT,
 
-    /// Other custom metadata
-    #[strum(default)]
-    Other(String),
+    /// Other custom metadata, with the FourCC bytes exactly as they were read
+    Other([u8; 4]),
+}
+
+impl Tag {
+    /// The four character code of the tag as it appears in the GPMF stream,
+    /// [`Display`](std::fmt::Display) gives the human readable name instead.
+    ///
+    /// Each byte of an unsupported tag becomes the char with the same code point,
+    /// use [`Tag::fourcc_bytes`] for the exact bytes.
+    pub fn fourcc(&self) -> String {
+        self.fourcc_bytes().iter().map(|c| *c as char).collect()
+    }
+
+    /// The four bytes of the tag as they appear in the GPMF stream
+    pub fn fourcc_bytes(&self) -> [u8; 4] {
+        match self {
+            Tag::Other(fourcc) => *fourcc,
+            tag => known_fourccs()
+                .get(&mem::discriminant(tag))
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The tag for the FourCC bytes as read from a GPMF stream
+    pub fn from_fourcc(fourcc: [u8; 4]) -> Tag {
+        known_tags()
+            .get(&fourcc)
+            .cloned()
+            .unwrap_or(Tag::Other(fourcc))
+    }
+
+    /// Whether the FourCC consists of exactly four printable ASCII characters,
+    /// so that it can be used as is in column names and keys
+    pub fn is_printable(&self) -> bool {
+        self.fourcc_bytes()
+            .iter()
+            .all(|b| b.is_ascii_graphic() || *b == b' ')
+    }
+}
+
+/// Lookup table from FourCC to the supported tags
+fn known_tags() -> &'static HashMap<[u8; 4], Tag> {
+    /// The table, built on first use
+    static KNOWN_TAGS: OnceLock<HashMap<[u8; 4], Tag>> = OnceLock::new();
+    KNOWN_TAGS.get_or_init(|| {
+        Tag::iter()
+            .filter(|tag| !matches!(tag, Tag::Other(_)))
+            // the known variants are named after their FourCC, which is all ASCII
+            .filter_map(|tag| Some((format!("{:?}", tag).into_bytes().try_into().ok()?, tag)))
+            .collect()
+    })
+}
+
+/// Lookup table from the supported tags to their FourCC
+fn known_fourccs() -> &'static HashMap<mem::Discriminant<Tag>, [u8; 4]> {
+    /// The table, built on first use
+    static KNOWN_FOURCCS: OnceLock<HashMap<mem::Discriminant<Tag>, [u8; 4]>> = OnceLock::new();
+    KNOWN_FOURCCS.get_or_init(|| {
+        known_tags()
+            .iter()
+            .map(|(fourcc, tag)| (mem::discriminant(tag), *fourcc))
+            .collect()
+    })
+}
+
+impl FromStr for Tag {
+    type Err = strum::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Ok(fourcc) = <[u8; 4]>::try_from(s.as_bytes()) else {
+            return Err(strum::ParseError::VariantNotFound);
+        };
+        Ok(known_tags()
+            .get(&fourcc)
+            .cloned()
+            .unwrap_or(Tag::Other(fourcc)))
+    }
+}
+
+impl TryFrom<&str> for Tag {
+    type Error = strum::ParseError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Tag::from_str(s)
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tag::Other(fourcc) => {
+                let escaped: String = fourcc
+                    .iter()
+                    .map(|b| match b {
+                        b' ' | b'!'..=b'~' if *b != b'\\' => (*b as char).to_string(),
+                        b => format!("\\x{:02X}", b),
+                    })
+                    .collect();
+                f.pad(&escaped)
+            }
+            tag => f.pad(tag.into()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -543,13 +1095,17 @@ mod tests {
 
     use std::path::Path;
 
-    use std::sync::Once;
+    use std::sync::{Arc, Mutex, Once};
     // use chrono::{TimeZone, Utc};
+    #[cfg(feature = "dates")]
     use chrono::{TimeZone, Utc};
 
+    #[cfg(feature = "fixedpoint")]
     use fixed::prelude::*;
+    #[cfg(feature = "fixedpoint")]
     use fixed::types::{I16F16, I32F32};
-    use tracing::Level;
+    use strum::IntoEnumIterator;
+    use tracing::{info, Level};
     use tracing_subscriber::FmtSubscriber;
 
     static INIT: Once = Once::new();
@@ -577,6 +1133,113 @@ mod tests {
         });
     }
 
+    /// Writer appending the log output to a shared buffer
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Run `f` and return its result along with the warnings and errors it logged
+    pub(crate) fn capture_warnings<T>(f: impl FnOnce() -> T) -> (T, String) {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let writer = buffer.clone();
+        let subscriber = FmtSubscriber::builder()
+            .with_max_level(Level::WARN)
+            .with_ansi(false)
+            .with_writer(move || LogBuffer(writer.clone()))
+            .finish();
+        let res = tracing::subscriber::with_default(subscriber, f);
+        let logs = String::from_utf8_lossy(&buffer.lock().unwrap()).into_owned();
+        (res, logs)
+    }
+
+    #[test]
+    fn test_tag_fourcc() {
+        Tag::iter()
+            .filter(|t| !matches!(t, Tag::Other(_)))
+            .for_each(|t| {
+                assert_eq!(t.fourcc().len(), 4);
+                assert_eq!(t.fourcc_bytes(), t.fourcc().as_bytes());
+                assert_eq!(Tag::try_from(t.fourcc().as_str()).unwrap(), t);
+            });
+        assert_eq!(Tag::Other(*b"abcd").fourcc(), "abcd");
+    }
+
+    #[test]
+    fn test_tag_from_str_length() {
+        assert_eq!(Tag::try_from("DEVC").unwrap(), Tag::DEVC);
+        assert_eq!(Tag::try_from("abcd").unwrap(), Tag::Other(*b"abcd"));
+        assert!(Tag::try_from("abc").is_err());
+        assert!(Tag::try_from("abcde").is_err());
+        assert!(Tag::try_from("").is_err());
+        // two bytes of UTF-8 plus two ASCII is still four bytes
+        assert!(Tag::try_from("é1").is_err());
+        assert!(Tag::try_from("é12").is_ok());
+    }
+
+    #[test]
+    fn test_tag_display_escaped() {
+        let tags = [
+            Tag::from_fourcc([b'A', 0x00, b'B', b'C']),
+            Tag::from_fourcc([0xFF, 0xFF, 0xFF, 0xFF]),
+            Tag::from_fourcc([0xC3, 0xA9, b'1', b'2']),
+            Tag::from_fourcc(*b"a\\\","),
+            Tag::try_from("é12").unwrap(),
+        ];
+        for tag in &tags {
+            let display = tag.to_string();
+            assert!(display.bytes().all(|b| b.is_ascii_graphic()), "{}", display);
+        }
+        assert_eq!(tags[0].to_string(), "A\\x00BC");
+        assert_eq!(tags[1].to_string(), "\\xFF\\xFF\\xFF\\xFF");
+        assert_eq!(tags[2].to_string(), "\\xC3\\xA912");
+        assert_eq!(tags[3].to_string(), "a\\x5C\",");
+        assert_eq!(tags[4].to_string(), "\\xC3\\xA912");
+
+        assert!(!tags[0].is_printable());
+        assert!(!tags[1].is_printable());
+        assert!(!tags[2].is_printable());
+        assert!(tags[3].is_printable());
+        assert!(!tags[4].is_printable());
+        assert!(Tag::from_fourcc(*b"abcd").is_printable());
+        assert!(Tag::DEVC.is_printable());
+        assert_eq!(Tag::from_fourcc(*b"DEVC"), Tag::DEVC);
+        assert_eq!(Tag::DEVC.to_string(), "Device");
+    }
+
+    #[test]
+    fn test_value_kind() {
+        assert_eq!(Value::U8(1).kind(), ValueKind::U8);
+        assert_eq!(Value::Strings(Vec::new()).kind(), ValueKind::Strings);
+        assert_eq!(Value::Nested(Vec::new()).kind(), ValueKind::Nested);
+        assert_eq!(Value::Empty(Type::U32).kind(), ValueKind::Empty);
+        assert_eq!(Value::Empty(Type::U32).datatype(), Some(Type::U32));
+    }
+
+    #[test]
+    fn test_datatype() {
+        let simple = Value::Simple(vec![vec![Value::I16(1), Value::I16(2)]]);
+        assert_eq!(simple.datatype(), Some(Type::I16));
+        assert_eq!(Value::Simple(Vec::new()).datatype(), None);
+        assert_eq!(Value::Strings(Vec::new()).datatype(), Some(Type::Char));
+        assert_eq!(Value::Type(vec![Type::U32]).datatype(), Some(Type::Char));
+        assert_eq!(Value::Nested(Vec::new()).datatype(), Some(Type::Nested));
+        let raw = |type_char| Value::Raw {
+            type_char,
+            bytes: Vec::new(),
+        };
+        assert_eq!(raw(b'L').datatype(), Some(Type::U32));
+        assert_eq!(raw(b'x').datatype(), None);
+    }
+
     #[test]
     fn test_type() {
         setup();
@@ -621,6 +1284,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::byte_char_slices)]
     fn test_take_until_no_null() {
         let data = [b'a', b'b', b'c'];
         let until_null: Vec<_> = data.into_iter().take_while(|b| *b != 0).collect();
@@ -634,6 +1298,7 @@ mod tests {
     //use fixed::types::I16F16; //is a 32-bit fixed-point signed number with 20 integer bits and 12 fractional bits
 
     #[test]
+    #[cfg(feature = "fixedpoint")]
     fn test_q32() {
         //-32768.0 to 32767.99998
         let max = I16F16::from_bits(i32::MAX);
@@ -660,6 +1325,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "fixedpoint")]
     fn test_q64() {
         //-32768.0 to 32767.99998
         let max = I32F32::from_bits(i64::MAX);
@@ -686,6 +1352,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "dates")]
     fn test_date() {
         //16 byte
         // yymmddhhmmss.sss
@@ -0,0 +1,121 @@
+//! Removing streams from the parsed entries, e.g. the location before sharing the metadata
+//!
+//! The writer derives the lengths of the containers from their children, so the stripped entries
+//! can be written back with [`write_gpmf`](crate::byteorder_gpmf::write_gpmf).
+
+use crate::{KeyValue, Tag, Value};
+use tracing::debug;
+
+/// The tags of the GPS streams, the default for [`strip_streams`]
+pub const GPS_TAGS: [Tag; 5] = [Tag::GPS5, Tag::GPS9, Tag::GPSU, Tag::GPSF, Tag::GPSP];
+
+/// Remove every STRM holding an entry with one of the `tags`, at any depth
+///
+/// Entries with one of the tags outside of a STRM are removed as well.
+/// The DEVC containers are kept, also when all of their streams are removed.
+pub fn strip_streams(entries: Vec<KeyValue>, tags: &[Tag]) -> Vec<KeyValue> {
+    entries
+        .into_iter()
+        .filter(|kv| {
+            let strip = tags.contains(&kv.key)
+                || kv.key == Tag::STRM
+                    && matches!(&kv.value, Value::Nested(children)
+                        if children.iter().any(|child| tags.contains(&child.key)));
+            if strip {
+                debug!("Stripping {} at {:?}", kv.key, kv.offset());
+            }
+            !strip
+        })
+        .map(|kv| match kv.value {
+            Value::Nested(children) => {
+                KeyValue::new(kv.key, Value::Nested(strip_streams(children, tags)))
+            }
+            _ => kv,
+        })
+        .collect()
+}
+
+/// Remove the GPS streams, see [`GPS_TAGS`]
+pub fn strip_gps(entries: Vec<KeyValue>) -> Vec<KeyValue> {
+    strip_streams(entries, &GPS_TAGS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::GpmfBuilder;
+    use crate::byteorder_gpmf::{parse_gpmf, write_gpmf};
+    use std::path::Path;
+
+    /// Whether any entry has one of the `tags`, at any depth
+    fn contains_any(entries: &[KeyValue], tags: &[Tag]) -> bool {
+        entries.iter().any(|kv| {
+            tags.contains(kv.key())
+                || matches!(kv.value(), Value::Nested(children) if contains_any(children, tags))
+        })
+    }
+
+    /// The number of STRM containers, at any depth
+    fn count_streams(entries: &[KeyValue]) -> usize {
+        entries
+            .iter()
+            .map(|kv| match kv.value() {
+                Value::Nested(children) => {
+                    usize::from(kv.key() == &Tag::STRM) + count_streams(children)
+                }
+                _ => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_strip_gps() {
+        let text = std::fs::read(Path::new("samples").join("hero5.raw")).unwrap();
+        let entries = parse_gpmf(&text).unwrap();
+        assert!(contains_any(&entries, &GPS_TAGS));
+        let streams = count_streams(&entries);
+
+        let stripped = strip_gps(entries);
+        assert!(!contains_any(&stripped, &GPS_TAGS));
+        // only the GPS stream is gone
+        assert_eq!(count_streams(&stripped), streams - 1);
+
+        let written = write_gpmf(&stripped).unwrap();
+        assert!(written.len() < text.len());
+        let reparsed = parse_gpmf(&written).unwrap();
+        assert!(!contains_any(&reparsed, &GPS_TAGS));
+        assert_eq!(count_streams(&reparsed), streams - 1);
+    }
+
+    #[test]
+    fn test_strip_gps9() {
+        // there is no sample from a camera with GPS9, so build one
+        let gps9 = [[335_068_213_i32, -1_179_453_490, 6342, 0, 12, 8_000, 90_000]];
+        let bytes = GpmfBuilder::device(1, "Camera")
+            .stream("Accelerometer", |s| {
+                s.samples_i16(Tag::ACCL, &[[1_i16, 2, 3]])
+            })
+            .stream("GPS (Lat., Long., Alt., 2D, 3D, days, secs)", |s| {
+                s.samples_i32(Tag::GPS9, &gps9)
+            })
+            .to_bytes()
+            .unwrap();
+        let entries = parse_gpmf(&bytes).unwrap();
+        assert!(contains_any(&entries, &[Tag::GPS9]));
+
+        let reparsed = parse_gpmf(&write_gpmf(&strip_gps(entries)).unwrap()).unwrap();
+        assert!(!contains_any(&reparsed, &GPS_TAGS));
+        assert_eq!(count_streams(&reparsed), 1);
+        assert!(contains_any(&reparsed, &[Tag::ACCL, Tag::DVNM]));
+    }
+
+    #[test]
+    fn test_strip_tags() {
+        let text = std::fs::read(Path::new("samples").join("hero5.raw")).unwrap();
+        let entries = parse_gpmf(&text).unwrap();
+        let stripped = strip_streams(entries, &[Tag::ACCL, Tag::GYRO]);
+        assert!(!contains_any(&stripped, &[Tag::ACCL, Tag::GYRO]));
+        assert!(contains_any(&stripped, &[Tag::GPS5]));
+        parse_gpmf(&write_gpmf(&stripped).unwrap()).unwrap();
+    }
+}
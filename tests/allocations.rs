@@ -0,0 +1,80 @@
+//! Counting the allocations made while parsing KLV headers
+//!
+//! The counting allocator replaces the global allocator of this test binary only.
+
+use gpmf::byteorder_gpmf::{parse_gpmf, parse_gpmf_opts, ParseOptions, UnknownTags};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Allocator counting the allocations made on the current thread
+struct CountingAlloc;
+
+thread_local! {
+    /// Number of allocations made on this thread, the tests run on several threads
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+/// The number of allocations made by `f` on this thread
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+/// `count` KLV entries of `tag` with a U32 of data each
+fn entries(tag: &[u8; 4], count: usize) -> Vec<u8> {
+    let entry = [&tag[..], &[b'L', 4, 0, 1], &[0, 0, 0, 1]].concat();
+    entry.repeat(count)
+}
+
+/// `count` KLV entries of `tag` without samples
+fn empty_entries(tag: &[u8; 4], count: usize) -> Vec<u8> {
+    let entry = [&tag[..], &[b'L', 4, 0, 0]].concat();
+    entry.repeat(count)
+}
+
+#[test]
+fn test_skipped_headers_do_not_allocate() {
+    let options = ParseOptions {
+        unknown_tags: UnknownTags::Skip,
+        ..Default::default()
+    };
+    let few = entries(b"ZZZZ", 64);
+    let many = entries(b"ZZZZ", 1024);
+    // build the tag lookup table outside of the measurement
+    parse_gpmf(&few).unwrap();
+
+    // the parse allocates the same, no matter how many headers it reads
+    let parse = |input: &[u8]| {
+        allocations(|| assert!(parse_gpmf_opts(input, &options).unwrap().is_empty()))
+    };
+    assert_eq!(parse(&many), parse(&few));
+    tracing::subscriber::with_default(tracing::subscriber::NoSubscriber::default(), || {
+        assert_eq!(parse(&many), parse(&few));
+    });
+}
+
+#[test]
+fn test_known_headers_do_not_allocate() {
+    let few = empty_entries(b"TSMP", 64);
+    let many = empty_entries(b"TSMP", 128);
+    parse_gpmf(&few).unwrap();
+
+    // entries without samples only take space in the result, which grows once from 64 to 128
+    let parse = |input: &[u8]| allocations(|| assert!(!parse_gpmf(input).unwrap().is_empty()));
+    assert!(parse(&many) <= parse(&few) + 1);
+}